@@ -1,4 +1,6 @@
-use clap::{Parser, Subcommand};
+use crate::utils::file::ExistingFilePolicy;
+use crate::utils::rate::RateLimiter;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 /// LAN Transfer
@@ -15,6 +17,18 @@ pub enum Command {
         /// As a server, provide a root dir for the client to operate
         #[arg(short, long)]
         root_dir: PathBuf,
+
+        /// As a server, the max chunk size in bytes a client may use, accepts K/M/G suffixes
+        #[arg(short, long, default_value = "64M", value_parser = parse_size)]
+        max_chunk_size: usize,
+
+        /// As a server, the max rate in bytes per second of chunk data over all connections, accepts K/M/G suffixes
+        #[arg(long, value_parser = parse_size)]
+        limit_rate: Option<usize>,
+
+        /// As a server, the max rate in bytes per second of chunk data on each connection, accepts K/M/G suffixes
+        #[arg(long, value_parser = parse_size)]
+        limit_rate_per_conn: Option<usize>,
     },
     /// Execute a lant client command
     Client {
@@ -30,29 +44,317 @@ pub enum Command {
 #[derive(Subcommand, Debug)]
 pub enum ClientCommand {
     /// List the contents of the specified path
+    #[command(disable_help_flag = true)]
     Ls {
         /// Gets the contents of the given path
         #[arg(short, long)]
         remote_path: PathBuf,
+
+        /// Show the mode, size, modification time and link target of each item
+        #[arg(short, long)]
+        long: bool,
+
+        /// Show sizes with units, in the long format
+        #[arg(short = 'h', long)]
+        human_readable: bool,
+
+        /// Order of the items, the largest or newest first when not by name
+        #[arg(long, value_enum, default_value_t = LsSort::Name)]
+        sort: LsSort,
+
+        /// Print help
+        #[arg(long, action = clap::ArgAction::Help)]
+        help: Option<bool>,
+    },
+    /// Show the type, size, modification time and mode of the specified path
+    Stat {
+        /// Remote path to describe, a link is described rather than its target
+        #[arg(short, long)]
+        remote_path: PathBuf,
+
+        /// Print the record as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Make a dir at the specified path
+    Mkdir {
+        /// Remote dir to make
+        #[arg(short, long)]
+        remote_path: PathBuf,
+
+        /// Make the missing parent dirs as well, and accept a dir that already exists
+        #[arg(short, long)]
+        parents: bool,
     },
-    /// Put a file to the specified path
+    /// Remove a file or a dir at the specified path
+    Rm {
+        /// Remote file or dir to remove
+        #[arg(short = 'p', long)]
+        remote_path: PathBuf,
+
+        /// Remove a dir with everything in it, otherwise only an empty dir is removed
+        #[arg(short, long)]
+        recursive: bool,
+
+        /// Remove without asking for confirmation
+        #[arg(long)]
+        force: bool,
+    },
+    /// Move or rename a file or a dir within the server root
+    Mv {
+        /// Remote file or dir to move
+        #[arg(short, long)]
+        src_path: PathBuf,
+
+        /// Remote path it is moved to, an existing dir takes it under the same name
+        #[arg(short, long)]
+        dst_path: PathBuf,
+
+        #[command(flatten)]
+        options: MvArgs,
+    },
+    /// Put a file or a dir to the specified path
     Put {
-        /// Local file that need to push
+        /// Local file or dir that need to push, a dir is pushed recursively, '-' reads stdin
         #[arg(short, long)]
         file: PathBuf,
 
         /// Remote dir where the file push to
         #[arg(short, long)]
         remote_dir: PathBuf,
+
+        /// Name of the remote file, required when reading stdin
+        #[arg(long, required_if_eq("file", "-"))]
+        remote_name: Option<String>,
+
+        #[command(flatten)]
+        options: TransferArgs,
     },
-    /// Get a file from the specified path
+    /// Get a file or a dir from the specified path
     Get {
-        /// Remote file that need to get
+        /// Remote file or dir that need to get, a dir is got recursively
         #[arg(short, long)]
         file: PathBuf,
 
-        /// Local dir where the file save on
+        /// Local dir where the file save on, '-' writes a single file to stdout
         #[arg(short, long)]
         local_dir: PathBuf,
+
+        #[command(flatten)]
+        options: TransferArgs,
     },
+    /// Mirror a local dir to a remote dir, only new or changed files are put
+    Sync {
+        /// Local dir whose contents are mirrored
+        #[arg(short, long)]
+        local_dir: PathBuf,
+
+        /// Remote dir that mirrors the local dir
+        #[arg(short, long)]
+        remote_dir: PathBuf,
+
+        #[command(flatten)]
+        sync_options: SyncArgs,
+
+        #[command(flatten)]
+        options: TransferArgs,
+    },
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct MvArgs {
+    /// Replace an existing destination of the same type
+    #[arg(long, group = "existing")]
+    pub overwrite: bool,
+
+    /// Leave an existing destination as it is, and skip the move
+    #[arg(long, group = "existing")]
+    pub skip_existing: bool,
+
+    /// Move to a new name with a numbered suffix when the destination exists
+    #[arg(long, group = "existing")]
+    pub rename: bool,
+
+    /// Fail when the destination exists, the default
+    #[arg(long, group = "existing")]
+    pub fail_if_exists: bool,
+}
+
+impl MvArgs {
+    pub fn existing_file_policy(&self) -> ExistingFilePolicy {
+        if self.overwrite {
+            ExistingFilePolicy::Overwrite
+        } else if self.skip_existing {
+            ExistingFilePolicy::SkipExisting
+        } else if self.rename {
+            ExistingFilePolicy::Rename
+        } else {
+            ExistingFilePolicy::FailIfExists
+        }
+    }
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct SyncArgs {
+    /// Compare files of the same size by digest, instead of by modification time
+    #[arg(long, conflicts_with = "two_way")]
+    pub checksum: bool,
+
+    /// Delete remote files and dirs that no longer exist locally
+    #[arg(long, conflicts_with = "two_way")]
+    pub delete: bool,
+
+    /// Sync both ways, changes since the last sync are taken from either side
+    #[arg(long)]
+    pub two_way: bool,
+
+    /// How a file changed on both sides is resolved in a two-way sync
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::KeepBoth)]
+    pub conflict: ConflictPolicy,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConflictPolicy {
+    /// Keep the remote version beside the local one, with a conflict suffix
+    KeepBoth,
+    /// Keep the version modified last
+    NewerWins,
+    /// Stop before changing anything
+    Abort,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LsSort {
+    Name,
+    Size,
+    Time,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum LinkPolicy {
+    /// Transfer what the link points to, a server link only when it stays inside the root
+    Follow,
+    /// Recreate the link itself on the other side
+    CopyAsLink,
+    /// Leave links out
+    #[default]
+    Skip,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct TransferArgs {
+    /// Number of chunks transferred at the same time
+    #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    pub parallel: u16,
+
+    /// Size of each chunk in bytes, accepts K/M/G suffixes, capped by the server
+    #[arg(short, long, default_value = "16M", value_parser = parse_size)]
+    pub chunk_size: usize,
+
+    /// Compress chunk data with zstd, if the server supports it
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Send only the changed blocks when the destination file differs, instead of restarting
+    #[arg(long)]
+    pub delta: bool,
+
+    /// What a recursive transfer does with symbolic links, a sync only follows or skips them
+    #[arg(long, value_enum, default_value_t = LinkPolicy::Skip)]
+    pub links: LinkPolicy,
+
+    /// Leave the destination file with the current time and default mode, instead of the source ones
+    #[arg(long)]
+    pub no_preserve: bool,
+
+    /// Max rate in bytes per second of chunk data, accepts K/M/G suffixes
+    #[arg(long, value_parser = parse_size)]
+    pub limit_rate: Option<usize>,
+
+    /// Resume an unfinished transfer, and replace an existing destination file only when it differs, the default
+    #[arg(long, group = "existing")]
+    pub resume: bool,
+
+    /// Replace an existing destination file
+    #[arg(long, group = "existing")]
+    pub overwrite: bool,
+
+    /// Leave an existing destination file as it is, and skip the transfer
+    #[arg(long, group = "existing")]
+    pub skip_existing: bool,
+
+    /// Transfer to a new name with a numbered suffix when the destination file exists
+    #[arg(long, group = "existing")]
+    pub rename: bool,
+
+    /// Fail when the destination file exists
+    #[arg(long, group = "existing")]
+    pub fail_if_exists: bool,
+}
+
+impl TransferArgs {
+    pub fn existing_file_policy(&self) -> ExistingFilePolicy {
+        if self.overwrite {
+            ExistingFilePolicy::Overwrite
+        } else if self.skip_existing {
+            ExistingFilePolicy::SkipExisting
+        } else if self.rename {
+            ExistingFilePolicy::Rename
+        } else if self.fail_if_exists {
+            ExistingFilePolicy::FailIfExists
+        } else {
+            ExistingFilePolicy::Resume
+        }
+    }
+
+    pub fn rate_limiter(&self) -> Option<RateLimiter> {
+        self.limit_rate.map(|rate| RateLimiter::new(rate as u64))
+    }
+}
+
+fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let (num, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((pos, _)) => size.split_at(pos),
+        None => (size, ""),
+    };
+    let num = num.parse::<usize>().map_err(|e| e.to_string())?;
+    let unit = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        unit => return Err(format!("unknown size unit '{unit}'")),
+    };
+    match num.checked_mul(unit) {
+        Some(0) => Err("size must be greater than 0".to_string()),
+        Some(size) => Ok(size),
+        None => Err("size is too large".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_with_units() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("1k"), Ok(1024));
+        assert_eq!(parse_size("8KB"), Ok(8 * 1024));
+        assert_eq!(parse_size("16M"), Ok(16 * 1024 * 1024));
+        assert_eq!(parse_size(" 2GiB "), Ok(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn parse_size_rejects_invalid() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("12X").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("99999999999999G").is_err());
+    }
 }
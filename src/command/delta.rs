@@ -0,0 +1,331 @@
+use crate::command::{CommandServer, StreamCommandServer};
+use crate::message::delta::*;
+use crate::message::*;
+use crate::quic::client::Client;
+use crate::utils::delta::*;
+use crate::utils::dir::is_single_component;
+use crate::utils::file::file_digest;
+use crate::utils::progress::is_interactive;
+use crate::utils::rate::RateLimiter;
+use crate::utils::stream::read_request_payload;
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Sends only the blocks of local file that the remote file does not have
+pub async fn put_delta(
+    client: &Client,
+    conn: &quinn::Connection,
+    local_file_path: &Path,
+    remote_dir: &Path,
+    limiter: Option<&RateLimiter>,
+    log: impl Fn(String),
+) -> Result<()> {
+    let file_name = local_file_path
+        .file_name()
+        .ok_or(anyhow!("got file name error"))?;
+    let remote_file_path = remote_dir.join(file_name);
+
+    // get remote signature
+    let req_payload = SignatureRequestPayload::new(&remote_file_path);
+    let response = client
+        .request(conn, MessageType::SignatureRequest, req_payload)
+        .await?;
+    let res_payload = client
+        .unwrap_message(&response, MessageType::SignatureResponse)?
+        .ok_or(anyhow!(
+            "got remote signature error, path={remote_file_path:?}"
+        ))?;
+    let signature = SignatureResponsePayload::from_payload(res_payload)?.signature;
+
+    // compute delta against local file, the remote checks its result by the local digest
+    let local_file = File::open(local_file_path)?;
+    let digest_file_path = local_file_path.to_path_buf();
+    let (local_file, signature, ops, digest) = tokio::task::spawn_blocking(move || {
+        let ops = compute_delta(&local_file, &signature)?;
+        let digest = file_digest(digest_file_path)?;
+        anyhow::Ok((local_file, signature, ops, digest))
+    })
+    .await??;
+    let data_size = literal_size(&ops);
+    log(format!(
+        "delta: {local_file_path:?}, literal={data_size}, total={}",
+        local_file.metadata()?.len()
+    ));
+
+    // send ops, the literal data is streamed after them
+    let meta = PatchRequestMeta::new(
+        file_name,
+        remote_dir,
+        signature.block_size,
+        ops.clone(),
+        digest,
+    );
+    let (mut ss, mut rs) = client
+        .request_stream(
+            conn,
+            MessageType::PatchRequest,
+            DeltaPayload::new(meta),
+            data_size,
+        )
+        .await?;
+    send_literals(&local_file, &ops, &mut ss, limiter).await?;
+    ss.finish()?;
+
+    // process response
+    let response = client.recv_message(&mut rs).await?;
+    let res_payload = client
+        .unwrap_message(&response, MessageType::PatchResponse)?
+        .ok_or(anyhow!(
+            "patch remote file failed, path={remote_file_path:?}"
+        ))?;
+    let payload = PatchResponsePayload::from_payload(res_payload)?;
+    if !is_interactive() {
+        log(format!("<<<: {payload:?}"));
+    }
+    Ok(())
+}
+
+/// Receives only the blocks of remote file that the local file does not have, the rebuilt file
+/// must have the remote `digest`
+pub async fn get_delta(
+    client: &Client,
+    conn: &quinn::Connection,
+    remote_file_path: &Path,
+    local_file_path: &Path,
+    digest: &str,
+    limiter: Option<&RateLimiter>,
+    log: impl Fn(String),
+) -> Result<()> {
+    // send local signature
+    let local_file = File::open(local_file_path)?;
+    let (local_file, signature) = tokio::task::spawn_blocking(move || {
+        let signature = FileSignature::compute(&local_file)?;
+        anyhow::Ok((local_file, signature))
+    })
+    .await??;
+    let req_payload = DeltaRequestPayload::new(remote_file_path, signature);
+    let (mut ss, mut rs) = client
+        .request_stream(conn, MessageType::DeltaRequest, req_payload, 0)
+        .await?;
+    ss.finish()?;
+
+    // receive ops, the literal data follows them
+    let payload_size = client
+        .recv_stream_header(&mut rs, MessageType::DeltaResponse)
+        .await?
+        .ok_or(anyhow!("got remote delta error, path={remote_file_path:?}"))?;
+    let (meta, data_size) = read_delta_meta::<DeltaResponseMeta>(&mut rs, payload_size).await?;
+    log(format!(
+        "delta: {local_file_path:?}, literal={data_size}, ops={}",
+        meta.ops.len()
+    ));
+
+    // rebuild the file aside, then replace the local one once verified
+    let temp_file_path = delta_temp_path(local_file_path)?;
+    let result = patch_file(
+        &local_file,
+        &temp_file_path,
+        meta.block_size,
+        &meta.ops,
+        data_size,
+        &mut rs,
+        limiter,
+    )
+    .await;
+    let result = match result {
+        Ok(_) => check_rebuilt_digest(&temp_file_path, digest).await,
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_file_path);
+    }
+    result?;
+    fs::rename(&temp_file_path, local_file_path)?;
+    Ok(())
+}
+
+async fn read_delta_meta<T: DeserializeOwned>(
+    rs: &mut quinn::RecvStream,
+    payload_size: MessagePayloadSize,
+) -> Result<(T, u64)> {
+    let mut meta_size_bytes = [0; SIZE_OF_DELTA_META_SIZE];
+    rs.read_exact(&mut meta_size_bytes).await?;
+    let meta_size = delta_meta_size(&meta_size_bytes)?;
+    let meta_bytes = read_request_payload(rs, meta_size as MessagePayloadSize).await?;
+    let meta = serde_json::from_slice::<T>(&meta_bytes)?;
+    let data_size = payload_size
+        .checked_sub((SIZE_OF_DELTA_META_SIZE + meta_size) as MessagePayloadSize)
+        .ok_or(anyhow!("payload size error"))?;
+    Ok((meta, data_size))
+}
+
+async fn patch_file(
+    basis: &File,
+    temp_file_path: &Path,
+    block_size: u64,
+    ops: &[DeltaOp],
+    data_size: u64,
+    rs: &mut quinn::RecvStream,
+    limiter: Option<&RateLimiter>,
+) -> Result<u64> {
+    if literal_size(ops) != data_size {
+        return Err(anyhow!("delta literal size error, size={data_size}"));
+    }
+    let temp_file = File::options()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(temp_file_path)?;
+    apply_delta(basis, block_size, &temp_file, ops, rs, limiter).await
+}
+
+/// The rebuilt file is only kept when it has the digest of the source file
+async fn check_rebuilt_digest(temp_file_path: &Path, digest: &str) -> Result<()> {
+    let digest_file_path = temp_file_path.to_path_buf();
+    let rebuilt_digest =
+        tokio::task::spawn_blocking(move || file_digest(digest_file_path)).await??;
+    if rebuilt_digest != digest {
+        return Err(anyhow!(
+            "delta result digest mismatch, expected={digest}, rebuilt={rebuilt_digest}"
+        ));
+    }
+    Ok(())
+}
+
+fn abs_file_path(abs_root_dir: &Path, file_path: &Path) -> Result<PathBuf> {
+    let mut abs_file_path = abs_root_dir.to_path_buf();
+    abs_file_path.push(file_path);
+    abs_file_path = abs_file_path.canonicalize()?;
+    if !abs_file_path.starts_with(abs_root_dir) || !abs_file_path.is_file() {
+        return Err(anyhow!("file not exists, path={file_path:?}"));
+    }
+    Ok(abs_file_path)
+}
+
+pub struct SignatureCommandServer(PathBuf);
+
+impl SignatureCommandServer {
+    pub fn new(abs_root_dir: PathBuf) -> Self {
+        Self(abs_root_dir)
+    }
+}
+
+impl CommandServer for SignatureCommandServer {
+    async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage> {
+        // deserialize request payload
+        let payload = SignatureRequestPayload::from_payload(payload)?;
+
+        // build response payload
+        let abs_file_path = abs_file_path(&self.0, &payload.remote_file_path)?;
+        let signature = tokio::task::spawn_blocking(move || {
+            FileSignature::compute(&File::open(abs_file_path)?)
+        })
+        .await??;
+        let res_payload = SignatureResponsePayload::new(signature);
+
+        // build response message
+        Ok(build_message(MessageType::SignatureResponse, res_payload))
+    }
+}
+
+pub struct PatchCommandServer(PathBuf, Option<RateLimiter>);
+
+impl PatchCommandServer {
+    pub fn new(abs_root_dir: PathBuf, limiter: Option<RateLimiter>) -> Self {
+        Self(abs_root_dir, limiter)
+    }
+}
+
+impl StreamCommandServer for PatchCommandServer {
+    async fn handle_stream(
+        &self,
+        payload_size: MessagePayloadSize,
+        rs: &mut quinn::RecvStream,
+        ss: &mut quinn::SendStream,
+    ) -> Result<()> {
+        // receive ops, the literal data follows them
+        let (meta, data_size) = read_delta_meta::<PatchRequestMeta>(rs, payload_size).await?;
+
+        // check file valid
+        if !is_single_component(&meta.file_name) {
+            return Err(anyhow!("file name invalid, name={:?}", meta.file_name));
+        }
+        let abs_file_path = abs_file_path(&self.0, &meta.remote_dir.join(&meta.file_name))?;
+
+        // rebuild the file aside, then replace the remote one once verified
+        let basis = File::open(&abs_file_path)?;
+        let temp_file_path = delta_temp_path(&abs_file_path)?;
+        let result = patch_file(
+            &basis,
+            &temp_file_path,
+            meta.block_size,
+            &meta.ops,
+            data_size,
+            rs,
+            self.1.as_ref(),
+        )
+        .await;
+        let result = match result {
+            Ok(file_len) => check_rebuilt_digest(&temp_file_path, &meta.digest)
+                .await
+                .map(|_| file_len),
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_file_path);
+        }
+        let file_len = result?;
+        fs::rename(&temp_file_path, &abs_file_path)?;
+
+        // send response back
+        let res_payload = PatchResponsePayload::new(file_len);
+        let mut response = build_message(MessageType::PatchResponse, res_payload);
+        ss.write_all_chunks(response.as_mut_slice()).await?;
+        Ok(())
+    }
+}
+
+pub struct DeltaCommandServer(PathBuf, Option<RateLimiter>);
+
+impl DeltaCommandServer {
+    pub fn new(abs_root_dir: PathBuf, limiter: Option<RateLimiter>) -> Self {
+        Self(abs_root_dir, limiter)
+    }
+}
+
+impl StreamCommandServer for DeltaCommandServer {
+    async fn handle_stream(
+        &self,
+        payload_size: MessagePayloadSize,
+        rs: &mut quinn::RecvStream,
+        ss: &mut quinn::SendStream,
+    ) -> Result<()> {
+        // deserialize request payload
+        let payload = read_request_payload(rs, payload_size).await?;
+        let payload = DeltaRequestPayload::from_payload(&payload)?;
+
+        // compute delta against the client signature
+        let abs_file_path = abs_file_path(&self.0, &payload.remote_file_path)?;
+        let file = File::open(abs_file_path)?;
+        let signature = payload.signature;
+        let (file, signature, ops) = tokio::task::spawn_blocking(move || {
+            let ops = compute_delta(&file, &signature)?;
+            anyhow::Ok((file, signature, ops))
+        })
+        .await??;
+        let data_size = literal_size(&ops);
+
+        // send response back, the literal data is streamed after the ops
+        let meta = DeltaResponseMeta::new(signature.block_size, ops.clone());
+        let mut response = build_stream_message(
+            MessageType::DeltaResponse,
+            DeltaPayload::new(meta),
+            data_size,
+        );
+        ss.write_all_chunks(response.as_mut_slice()).await?;
+        send_literals(&file, &ops, ss, self.1.as_ref()).await
+    }
+}
@@ -0,0 +1,101 @@
+use crate::command::CommandServer;
+use crate::message::digest::{DigestRequestPayload, DigestResponsePayload};
+use crate::message::*;
+use crate::quic::client::Client;
+use crate::utils::file::{file_digest, file_prefix_digest};
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+pub async fn check_remote_digest(
+    client: &Client,
+    conn: &quinn::Connection,
+    local_file_path: &Path,
+    remote_file_path: &Path,
+    log: impl Fn(String),
+) -> Result<()> {
+    let remote_digest = remote_file_digest(client, conn, remote_file_path).await?;
+    let local_digest = file_digest(local_file_path)?;
+    if local_digest != remote_digest {
+        return Err(anyhow!(
+            "file digest mismatch, local={local_file_path:?}({local_digest}), remote={remote_file_path:?}({remote_digest})"
+        ));
+    }
+    log(format!("digest ok: {local_file_path:?}, {local_digest}"));
+    Ok(())
+}
+
+pub async fn remote_prefix_matches(
+    client: &Client,
+    conn: &quinn::Connection,
+    local_file_path: &Path,
+    remote_file_path: &Path,
+    prefix_len: u64,
+) -> Result<bool> {
+    let req_payload = DigestRequestPayload::prefix(remote_file_path, prefix_len);
+    let remote_digest = remote_digest(client, conn, req_payload).await?;
+    let local_digest = file_prefix_digest(local_file_path, prefix_len)?;
+    Ok(local_digest == remote_digest)
+}
+
+pub async fn remote_file_digest(
+    client: &Client,
+    conn: &quinn::Connection,
+    remote_file_path: &Path,
+) -> Result<String> {
+    remote_digest(client, conn, DigestRequestPayload::new(remote_file_path)).await
+}
+
+async fn remote_digest(
+    client: &Client,
+    conn: &quinn::Connection,
+    req_payload: DigestRequestPayload,
+) -> Result<String> {
+    let remote_file_path = req_payload.remote_file_path.clone();
+    let response = client
+        .request(conn, MessageType::DigestRequest, req_payload)
+        .await?;
+    let res_payload = client
+        .unwrap_message(&response, MessageType::DigestResponse)?
+        .ok_or(anyhow!(
+            "got remote digest error, path={remote_file_path:?}"
+        ))?;
+    Ok(DigestResponsePayload::from_payload(res_payload)?.digest)
+}
+
+pub struct DigestCommandServer(PathBuf);
+
+impl DigestCommandServer {
+    pub fn new(abs_root_dir: PathBuf) -> Self {
+        Self(abs_root_dir)
+    }
+}
+
+impl CommandServer for DigestCommandServer {
+    async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage> {
+        // deserialize request payload
+        let payload = DigestRequestPayload::from_payload(payload)?;
+
+        // check file path valid
+        let mut abs_file_path = self.0.clone();
+        abs_file_path.push(payload.remote_file_path.clone());
+        abs_file_path = abs_file_path.canonicalize()?;
+        if !abs_file_path.starts_with(&self.0) || !abs_file_path.is_file() {
+            return Err(anyhow!(
+                "file not exists, path={:?}",
+                payload.remote_file_path
+            ));
+        }
+
+        // build response payload
+        let prefix_len = payload.prefix_len;
+        let digest = tokio::task::spawn_blocking(move || match prefix_len {
+            Some(prefix_len) => file_prefix_digest(&abs_file_path, prefix_len),
+            None => file_digest(&abs_file_path),
+        })
+        .await??;
+        let res_payload = DigestResponsePayload::new(digest);
+
+        // build response message
+        Ok(build_message(MessageType::DigestResponse, res_payload))
+    }
+}
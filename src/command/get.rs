@@ -1,144 +1,500 @@
-use crate::command::{CommandClient, CommandServer};
+use crate::cli::{LinkPolicy, TransferArgs};
+use crate::command::delta::get_delta;
+use crate::command::digest::{check_remote_digest, remote_file_digest, remote_prefix_matches};
+use crate::command::ls::ls_remote;
+use crate::command::{CommandClient, StreamCommandServer};
 use crate::message::get::*;
+use crate::message::ls::LsRequestPayload;
 use crate::message::*;
-use crate::quic::client::{Client, Stage};
+use crate::quic::client::Client;
+use crate::utils::codec::ChunkCodec;
+use crate::utils::dir::{
+    is_normal_path, link_in_root, make_dirs_in_root, unique_file_path, DirItem,
+};
 use crate::utils::file::*;
+use crate::utils::progress::{is_interactive, Progress};
+use crate::utils::rate::RateLimiter;
+use crate::utils::stream::{read_request_payload, recv_encoded_bytes, EncodedChunk, FileRange};
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use chrono::Local;
+use futures::{stream, StreamExt, TryStreamExt};
 use quinn::VarInt;
+use std::fs;
 use std::fs::File;
-use std::os::unix::fs::FileExt;
+use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
 
 pub struct GetCommandClient<'a> {
     client: &'a Client,
     file: PathBuf,
     local_dir: PathBuf,
+    options: TransferArgs,
+    limiter: Option<RateLimiter>,
+    progress: Progress,
 }
 
 impl<'a> GetCommandClient<'a> {
-    pub fn new(client: &'a Client, file: &Path, local_dir: &Path) -> Self {
+    pub fn new(client: &'a Client, file: &Path, local_dir: &Path, options: TransferArgs) -> Self {
         Self {
             client,
             file: file.to_path_buf(),
             local_dir: local_dir.to_path_buf(),
+            limiter: options.rate_limiter(),
+            progress: Progress::new(),
+            options,
         }
     }
 
     async fn do_request(&self) -> Result<()> {
-        println!(
-            "get file: {:?}, to local dir: {:?}, time:{}",
+        self.log(format!(
+            "get file: {:?}, to local dir: {:?}",
+            self.file, self.local_dir
+        ));
+
+        let conn = self.client.connecting()?.await?;
+        let mut req_payload = LsRequestPayload::recursive(&self.file);
+        req_payload.follow_links = self.options.links == LinkPolicy::Follow;
+        let listing = ls_remote(self.client, &conn, req_payload)
+            .await?
+            .ok_or(anyhow!("list remote path error, path={:?}", self.file))?;
+        if self.local_dir == Path::new(STDIO_PATH) {
+            if listing.is_dir {
+                return Err(anyhow!("only a file can be written to stdout"));
+            }
+            self.get_stdout(&conn, &self.file).await?;
+        } else if listing.is_dir {
+            let mut local_dir = self.local_dir.to_path_buf();
+            if let Some(dir_name) = self.file.file_name() {
+                local_dir.push(dir_name);
+            }
+            fs::create_dir_all(&local_dir)?;
+            let abs_local_dir = local_dir.canonicalize()?;
+            let mut links = Vec::new();
+            for item in listing.items {
+                // names come from the server, they must stay under the local dir
+                let rel_path = PathBuf::from(item.name());
+                if !is_normal_path(&rel_path) {
+                    return Err(anyhow!("item path invalid, path={rel_path:?}"));
+                }
+                let local_item_path = abs_local_dir.join(&rel_path);
+                if item.is_file() {
+                    let local_item_dir = local_item_path
+                        .parent()
+                        .ok_or(anyhow!("got local dir error"))?;
+                    let local_item_dir = make_dirs_in_root(&abs_local_dir, local_item_dir, true)
+                        .map_err(|e| anyhow!("{e}, path={rel_path:?}"))?;
+                    let remote_file_path = self.file.join(&rel_path);
+                    self.get_file(&conn, &remote_file_path, &local_item_dir)
+                        .await?;
+                } else if item.is_dir() {
+                    make_dirs_in_root(&abs_local_dir, &local_item_path, true)
+                        .map_err(|e| anyhow!("{e}, path={rel_path:?}"))?;
+                } else if item.is_symlink() {
+                    links.push((item, local_item_path));
+                }
+            }
+
+            // links are made last, so no file is written through one
+            for (item, local_link_path) in links {
+                self.get_link(&item, &abs_local_dir, &local_link_path)?;
+            }
+        } else {
+            fs::create_dir_all(&self.local_dir)?;
+            self.get_file(&conn, &self.file, &self.local_dir).await?;
+        }
+        conn.close(VarInt::from(200u32), "OK".as_bytes());
+
+        self.log(format!(
+            "get file: {:?}, to local dir: {:?} finish, {}",
             self.file,
             self.local_dir,
-            Local::now().timestamp_millis()
-        );
-        let file_name = self
-            .file
+            self.progress.summary()
+        ));
+
+        Ok(())
+    }
+
+    pub async fn get_file(
+        &self,
+        conn: &quinn::Connection,
+        remote_file_path: &Path,
+        local_dir: &Path,
+    ) -> Result<()> {
+        let file_name = remote_file_path
             .file_name()
             .ok_or(anyhow!("got file name error"))?;
-        let file_name = PathBuf::from(file_name);
-        let mut local_file_path = self.local_dir.to_path_buf();
-        local_file_path.push(&file_name);
-        let local_file_chunk_size = get_file_chunk_size(&local_file_path);
-        let mut req_payload = GetRequestPayload::new(&self.file, local_file_chunk_size);
+        let mut local_file_path = local_dir.join(file_name);
 
-        let conn = self.client.connecting()?.await?;
+        // apply the policy for an existing file
+        let mut restart = false;
+        if local_file_path.exists() {
+            match self.options.existing_file_policy() {
+                ExistingFilePolicy::Resume => {}
+                ExistingFilePolicy::Overwrite => restart = true,
+                ExistingFilePolicy::SkipExisting => {
+                    self.log(format!("skip existing file: {local_file_path:?}"));
+                    return Ok(());
+                }
+                ExistingFilePolicy::Rename => {
+                    local_file_path = unique_file_path(&local_file_path);
+                    self.log(format!("local file exists, get as: {local_file_path:?}"));
+                }
+                ExistingFilePolicy::FailIfExists => {
+                    return Err(anyhow!("file already exists, path={local_file_path:?}"));
+                }
+            }
+        }
+
+        // a complete local file is only replaced when it differs from remote
+        let part_file_path = part_file_path(&local_file_path)?;
+        if !restart && !part_file_path.exists() && local_file_path.exists() {
+            let remote_digest = remote_file_digest(self.client, conn, remote_file_path).await?;
+            if file_digest(&local_file_path)? == remote_digest {
+                self.log(format!("local file is up to date: {local_file_path:?}"));
+                return self
+                    .keep_remote_attrs(conn, remote_file_path, &local_file_path)
+                    .await;
+            }
+            if self.options.delta && fs::metadata(&local_file_path)?.len() > 0 {
+                self.log(format!(
+                    "local data mismatch, receive delta: {local_file_path:?}"
+                ));
+                get_delta(
+                    self.client,
+                    conn,
+                    remote_file_path,
+                    &local_file_path,
+                    &remote_digest,
+                    self.limiter.as_ref(),
+                    |message| self.log(message),
+                )
+                .await?;
+                return self
+                    .keep_remote_attrs(conn, remote_file_path, &local_file_path)
+                    .await;
+            }
+        }
+
+        // resume the part file, restart when its data differs from remote
+        let part_file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&part_file_path)?;
+        let mut done_len = part_done_len(&part_file_path)?;
+        if restart
+            || done_len > 0
+                && !remote_prefix_matches(
+                    self.client,
+                    conn,
+                    &part_file_path,
+                    remote_file_path,
+                    done_len,
+                )
+                .await?
+        {
+            if done_len > 0 {
+                self.log(format!("local data mismatch, restart: {local_file_path:?}"));
+            }
+            part_file.set_len(0)?;
+            remove_part_state(&part_file_path)?;
+            done_len = 0;
+        }
+
+        // the first chunk resumes from the data written without a gap, and tells the remote size
+        let part_file_chunk_size =
+            FileChunkSize::from_len(done_len as usize, self.options.chunk_size);
+        let codec = ChunkCodec::preferred(self.options.compress);
+        let req_payload = GetRequestPayload::new(remote_file_path, part_file_chunk_size, codec);
+        let (meta, _) = self
+            .get_chunk(conn, Some(&part_file_path), req_payload)
+            .await?;
+
+        // the rest chunks are transferred in parallel, with the chunk size the server chose
+        let next_index = meta.curr_trans_trunk_index + 1;
+        let total_chunks = meta.remote_file_chunk_size.total_chunks();
+        let chunk_unit_size = meta.remote_file_chunk_size.chunk_unit_size();
+        let remote_file_len = meta.remote_file_chunk_size.total_size() as u64;
+        self.progress.start_file(
+            &file_name.to_string_lossy(),
+            Some(remote_file_len),
+            index_offset(next_index, chunk_unit_size).min(remote_file_len),
+        )?;
+        stream::iter(next_index..total_chunks)
+            .map(|index| {
+                let req_payload =
+                    GetRequestPayload::chunk(remote_file_path, index, chunk_unit_size, codec);
+                self.get_chunk(conn, Some(&part_file_path), req_payload)
+            })
+            .buffer_unordered(self.options.parallel as usize)
+            .try_collect::<Vec<_>>()
+            .await?;
+        self.progress.finish_file();
+
+        // verify whole file, then move it to the final name
+        check_remote_digest(
+            self.client,
+            conn,
+            &part_file_path,
+            remote_file_path,
+            |message| self.log(message),
+        )
+        .await?;
+        fs::rename(&part_file_path, &local_file_path)?;
+        remove_part_state(&part_file_path)?;
+        if let (false, Some(attrs)) = (self.options.no_preserve, meta.attrs) {
+            attrs.permission_only().apply(&local_file_path)?;
+        }
+        Ok(())
+    }
+
+    /// A followed link is only listed as a link when it dangles, leaves root or loops
+    fn get_link(&self, item: &DirItem, abs_local_dir: &Path, local_link_path: &Path) -> Result<()> {
+        let target = item.link_target().ok_or(anyhow!("got link target error"))?;
+        match self.options.links {
+            LinkPolicy::CopyAsLink => {}
+            LinkPolicy::Follow => {
+                self.log(format!(
+                    "skip link that can not be followed: {local_link_path:?} -> {target:?}"
+                ));
+                return Ok(());
+            }
+            LinkPolicy::Skip => return Ok(()),
+        }
+        if !link_in_root(abs_local_dir, local_link_path, Path::new(target)) {
+            self.log(format!(
+                "skip link outside local dir: {local_link_path:?} -> {target:?}"
+            ));
+            return Ok(());
+        }
+
+        // apply the policy for an existing file
+        let mut local_link_path = local_link_path.to_path_buf();
+        if fs::symlink_metadata(&local_link_path).is_ok() {
+            match self.options.existing_file_policy() {
+                ExistingFilePolicy::Resume | ExistingFilePolicy::Overwrite => {
+                    fs::remove_file(&local_link_path)?;
+                }
+                ExistingFilePolicy::SkipExisting => {
+                    self.log(format!("skip existing file: {local_link_path:?}"));
+                    return Ok(());
+                }
+                ExistingFilePolicy::Rename => {
+                    local_link_path = unique_file_path(&local_link_path);
+                    self.log(format!("local file exists, get as: {local_link_path:?}"));
+                }
+                ExistingFilePolicy::FailIfExists => {
+                    return Err(anyhow!("file already exists, path={local_link_path:?}"));
+                }
+            }
+        }
+        symlink(target, &local_link_path)?;
+        Ok(())
+    }
+
+    /// The local file is complete, so the server answers with the remote attrs only
+    async fn keep_remote_attrs(
+        &self,
+        conn: &quinn::Connection,
+        remote_file_path: &Path,
+        local_file_path: &Path,
+    ) -> Result<()> {
+        if self.options.no_preserve {
+            return Ok(());
+        }
+        let local_file_chunk_size = get_file_chunk_size(local_file_path, self.options.chunk_size);
+        let req_payload =
+            GetRequestPayload::new(remote_file_path, local_file_chunk_size, ChunkCodec::Raw);
+        let (meta, _) = self
+            .get_chunk(conn, Some(local_file_path), req_payload)
+            .await?;
+        match meta.attrs {
+            Some(attrs) => attrs.permission_only().apply(local_file_path),
+            None => Ok(()),
+        }
+    }
+
+    /// Data goes into its chunk of the local file, or is returned without one
+    async fn get_chunk(
+        &self,
+        conn: &quinn::Connection,
+        local_file_path: Option<&Path>,
+        req_payload: GetRequestPayload,
+    ) -> Result<(GetResponseMeta, Vec<u8>)> {
+        let mut retries = 0;
         loop {
             // do request
-            println!(">>>: {req_payload:?}");
-            let response = self
+            if !is_interactive() {
+                self.log(format!(">>>: {req_payload:?}"));
+            }
+            let (mut ss, mut rs) = self
                 .client
-                .request(&conn, MessageType::GetRequest, req_payload)
+                .request_stream(conn, MessageType::GetRequest, req_payload.clone(), 0)
                 .await?;
+            ss.finish()?;
 
             // process response
-            if let Some(res_payload) = self
+            let payload_size = self
                 .client
-                .unwrap_message(&response, MessageType::GetResponse)?
+                .recv_stream_header(&mut rs, MessageType::GetResponse)
+                .await?
+                .ok_or(anyhow!(
+                    "get file failed, file={:?}",
+                    req_payload.remote_file_path
+                ))?;
+            match self
+                .process_response(local_file_path, payload_size, &mut rs)
+                .await
             {
-                let stage = self.process_response(&file_name, res_payload)?;
-                match stage {
-                    Stage::Processing(local_file_chunk_size) => {
-                        req_payload = GetRequestPayload::new(&self.file, local_file_chunk_size);
-                    }
-                    Stage::Finish => break,
+                Err(e) if ChunkError::is_checksum_mismatch(&e) && retries < MAX_CHUNK_RETRIES => {
+                    // request the same chunk again
+                    retries += 1;
+                    self.log(format!("[WARN]{e}, retry={retries}"));
                 }
-            } else {
-                break;
+                result => return result,
             }
         }
-        conn.close(VarInt::from(200u32), "OK".as_bytes());
-
-        println!(
-            "get file: {:?}, to local dir: {:?} finish, time:{}",
-            self.file,
-            self.local_dir,
-            Local::now().timestamp_millis()
-        );
-
-        Ok(())
     }
 
-    fn process_response(
+    async fn process_response(
         &self,
-        file_name: &Path,
-        payload: MessagePayloadRef,
-    ) -> Result<Stage<FileChunkSize>> {
-        // get payload
-        let payload = GetResponsePayloadRef::from_payload(payload)?;
-        println!("<<<: {:?}", payload.meta);
-
-        // build local file path
-        let mut local_file_path = self.local_dir.to_path_buf();
-        local_file_path.push(file_name);
-
-        // open local file
+        local_file_path: Option<&Path>,
+        payload_size: MessagePayloadSize,
+        rs: &mut quinn::RecvStream,
+    ) -> Result<(GetResponseMeta, Vec<u8>)> {
+        // get meta, the chunk data follows it
+        let mut meta_size_bytes = [0; SIZE_OF_GET_META_SIZE];
+        rs.read_exact(&mut meta_size_bytes).await?;
+        let meta_size = GetResponseMeta::meta_size(&meta_size_bytes)?;
+        let meta_bytes = read_request_payload(rs, meta_size as MessagePayloadSize).await?;
+        let meta = GetResponseMeta::from_meta_bytes(&meta_bytes)?;
+        let data_size = payload_size
+            .checked_sub((SIZE_OF_GET_META_SIZE + meta_size) as MessagePayloadSize)
+            .ok_or(anyhow!("payload size error"))?;
+        if !is_interactive() {
+            self.log(format!("<<<: {meta:?}"));
+        }
+        if data_size == 0 {
+            return Ok((meta, Vec::new()));
+        }
+
+        // stream data into the part file, a mismatched chunk is rewritten by the retry
+        let chunk_unit_size = meta.remote_file_chunk_size.chunk_unit_size();
+        let Some(local_file_path) = local_file_path else {
+            let data = recv_encoded_bytes(
+                rs,
+                meta.codec,
+                data_size,
+                chunk_unit_size as u64,
+                self.limiter.as_ref(),
+            )
+            .await?;
+            if crc32fast::hash(&data) != meta.chunk_checksum {
+                return Err(ChunkError::ChecksumMismatch(meta.curr_trans_trunk_index).into());
+            }
+            self.progress.inc(data.len() as u64);
+            return Ok((meta, data));
+        };
         let local_file = File::options()
             .create(true)
-            .append(true)
+            .truncate(false)
+            .write(true)
             .open(local_file_path)?;
+        let offset = index_offset(meta.curr_trans_trunk_index, chunk_unit_size);
+        let chunk = FileRange::new(&local_file, offset, chunk_unit_size as u64);
+        if chunk
+            .recv_encoded_from(rs, meta.codec, data_size, self.limiter.as_ref())
+            .await?
+            != meta.chunk_checksum
+        {
+            return Err(ChunkError::ChecksumMismatch(meta.curr_trans_trunk_index).into());
+        }
+        let chunk_size = meta
+            .remote_file_chunk_size
+            .chunk_size(meta.curr_trans_trunk_index) as u64;
+        add_part_range(local_file_path, offset, chunk_size)?;
+        self.progress.inc(chunk_size);
+        Ok((meta, Vec::new()))
+    }
+
+    /// Chunks are got in parallel but written in order, and there is nothing to resume
+    async fn get_stdout(&self, conn: &quinn::Connection, remote_file_path: &Path) -> Result<()> {
+        // the first chunk tells the remote size
+        let codec = ChunkCodec::preferred(self.options.compress);
+        let mut req_payload = GetRequestPayload::new(
+            remote_file_path,
+            FileChunkSize::from_len(0, self.options.chunk_size),
+            codec,
+        );
+        req_payload.sparse = false;
+        let (meta, data) = self.get_chunk(conn, None, req_payload).await?;
+        let mut stdout = tokio::io::stdout();
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&data);
+        stdout.write_all(&data).await?;
 
-        // append data
-        if !payload.data.is_empty() {
-            let offset = index_offset(payload.meta.curr_trans_trunk_index);
-            local_file.write_all_at(payload.data, offset)?;
+        // the rest chunks are written as soon as the ones before them are
+        let chunk_unit_size = meta.remote_file_chunk_size.chunk_unit_size();
+        let mut chunks = stream::iter(1..meta.remote_file_chunk_size.total_chunks())
+            .map(|index| {
+                let mut req_payload =
+                    GetRequestPayload::chunk(remote_file_path, index, chunk_unit_size, codec);
+                req_payload.sparse = false;
+                self.get_chunk(conn, None, req_payload)
+            })
+            .buffered(self.options.parallel as usize);
+        while let Some((_, data)) = chunks.try_next().await? {
+            hasher.update(&data);
+            stdout.write_all(&data).await?;
         }
+        stdout.flush().await?;
+        self.progress.finish_file();
 
-        // write analyse
-        let stage = if payload.meta.remote_file_chunk_size.total_chunks()
-            == payload.meta.curr_trans_trunk_index + 1
-        {
-            Stage::Finish
-        } else {
-            Stage::Processing(FileChunkSize::from(local_file.metadata()?.len() as usize))
-        };
+        // verify whole file
+        let local_digest = hasher.finalize().to_hex().to_string();
+        let remote_digest = remote_file_digest(self.client, conn, remote_file_path).await?;
+        if local_digest != remote_digest {
+            return Err(anyhow!(
+                "file digest mismatch, local=stdout({local_digest}), remote={remote_file_path:?}({remote_digest})"
+            ));
+        }
+        self.log(format!("digest ok: stdout, {local_digest}"));
+        Ok(())
+    }
 
-        Ok(stage)
+    /// Logs go to stderr while stdout carries the file data
+    fn log(&self, message: String) {
+        if self.local_dir == Path::new(STDIO_PATH) {
+            eprintln!("{message}");
+        } else {
+            self.progress.println(&message);
+        }
     }
 }
 
 impl<'a> CommandClient for GetCommandClient<'a> {
-    async fn request(&self) {
-        if let Err(e) = self.do_request().await {
-            println!("[ERR][Client] Process request error, error={e}");
-        }
+    async fn request(&self) -> Result<()> {
+        self.do_request().await.inspect_err(|e| {
+            self.log(format!("[ERR][Client] Process request error, error={e}"));
+        })
     }
 }
 
-pub struct GetCommandServer(PathBuf);
+pub struct GetCommandServer(PathBuf, usize, Option<RateLimiter>);
 
 impl GetCommandServer {
-    pub fn new(abs_root_dir: PathBuf) -> Self {
-        Self(abs_root_dir)
+    pub fn new(
+        abs_root_dir: PathBuf,
+        max_chunk_unit_size: usize,
+        limiter: Option<RateLimiter>,
+    ) -> Self {
+        Self(abs_root_dir, max_chunk_unit_size, limiter)
     }
 }
 
-impl CommandServer for GetCommandServer {
-    async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage> {
-        // deserialize request payload
-        let payload = GetRequestPayload::from_payload(payload)?;
-
+impl GetCommandServer {
+    fn locate_chunk(&self, payload: &GetRequestPayload) -> Result<(File, GetResponseMeta, u64)> {
         // check file path valid
         let mut file_path_valid = true;
         let mut abs_file_path = self.0.clone();
@@ -151,37 +507,80 @@ impl CommandServer for GetCommandServer {
             file_path_valid = false;
         }
 
-        // build response payload
-        let res_payload = if file_path_valid {
-            let file = File::open(&abs_file_path)?;
-            let file_len = file.metadata()?.len();
-            let file_chunked_size = FileChunkSize::from(file_len as usize);
-            let local_file_chunked_size = payload.local_file_chunk_size;
-            if local_file_chunked_size != file_chunked_size {
-                let mut curr_tarns_trunk_index = local_file_chunked_size.total_chunks();
-                if local_file_chunked_size.rest_size() != 0 {
-                    curr_tarns_trunk_index -= 1;
-                }
-                let meta = GetResponseMeta::new(file_chunked_size, curr_tarns_trunk_index);
-                let offset = index_offset(curr_tarns_trunk_index);
-                let buffer_size = buffer_size((file_len - offset) as usize);
-                let mut buffer = vec![0; buffer_size];
-                let _ = file.read_at(&mut buffer, offset)?;
-                GetResponsePayload::new(meta, Bytes::from(buffer))
-            } else {
-                return Err(anyhow!(
-                    "the file transfer is already completed, path={:?}",
-                    payload.remote_file_path
-                ));
-            }
-        } else {
+        if !file_path_valid {
             return Err(anyhow!(
                 "file not exists, path={:?}",
                 payload.remote_file_path
             ));
+        }
+
+        // pick the chunk to transfer
+        let file = File::open(&abs_file_path)?;
+        let file_len = file.metadata()?.len();
+        let chunk_unit_size = negotiate_chunk_unit_size(payload.chunk_unit_size, self.1);
+        let file_chunked_size = FileChunkSize::from_len(file_len as usize, chunk_unit_size);
+        let curr_tarns_trunk_index = match payload.chunk_index {
+            Some(index) => index,
+            None => {
+                let local_file_len = payload.local_file_chunk_size.total_size();
+                let local_file_chunked_size =
+                    FileChunkSize::from_len(local_file_len, chunk_unit_size);
+                if local_file_chunked_size == file_chunked_size {
+                    // already completed, nothing more to transfer
+                    let meta = GetResponseMeta::new(
+                        file_chunked_size.clone(),
+                        file_chunked_size.last_chunk_index(),
+                        0,
+                    );
+                    return Ok((file, meta, 0));
+                }
+                local_file_chunked_size.integer_chunks()
+            }
         };
+        if curr_tarns_trunk_index >= file_chunked_size.total_chunks() {
+            return Err(anyhow!(
+                "chunk index out of range, path={:?}, index={curr_tarns_trunk_index}",
+                payload.remote_file_path
+            ));
+        }
+
+        // locate chunk
+        let offset = index_offset(curr_tarns_trunk_index, chunk_unit_size);
+        let chunk_size = buffer_size((file_len - offset) as usize, chunk_unit_size) as u64;
+        let chunk_checksum = FileRange::new(&file, offset, chunk_size).checksum()?;
+        let meta = GetResponseMeta::new(file_chunked_size, curr_tarns_trunk_index, chunk_checksum);
+        Ok((file, meta, chunk_size))
+    }
+}
+
+impl StreamCommandServer for GetCommandServer {
+    async fn handle_stream(
+        &self,
+        payload_size: MessagePayloadSize,
+        rs: &mut quinn::RecvStream,
+        ss: &mut quinn::SendStream,
+    ) -> Result<()> {
+        // deserialize request payload
+        let payload = read_request_payload(rs, payload_size).await?;
+        let payload = GetRequestPayload::from_payload(&payload)?;
+
+        // locate chunk
+        let (file, mut meta, chunk_size) = self.locate_chunk(&payload)?;
+        meta.attrs = Some(FileAttrs::from_metadata(&file.metadata()?));
+        let offset = index_offset(
+            meta.curr_trans_trunk_index,
+            meta.remote_file_chunk_size.chunk_unit_size(),
+        );
+
+        // compress the chunk or skip its holes if the client accepts it
+        let chunk = FileRange::new(&file, offset, chunk_size);
+        let data = EncodedChunk::new(&chunk, payload.codec, payload.sparse)?;
+        meta.codec = data.codec();
 
-        // build response message
-        Ok(build_message(MessageType::GetResponse, res_payload))
+        // send response back, the chunk data is streamed after the meta
+        let mut response =
+            build_stream_message(MessageType::GetResponse, Bytes::from(meta), data.size());
+        ss.write_all_chunks(response.as_mut_slice()).await?;
+        data.send_to(ss, self.2.as_ref()).await
     }
 }
@@ -1,16 +1,23 @@
+use crate::cli::LsSort;
 use crate::command::{CommandClient, CommandServer};
 use crate::message::ls::{LsRequestPayload, LsResponsePayload};
 use crate::message::*;
 use crate::quic::client::Client;
-use crate::utils::dir::{DirItem, DirItemType};
+use crate::utils::dir::{join_in_root, permissions_string, DirItem};
 use anyhow::{anyhow, Result};
+use chrono::{Local, TimeZone};
+use indicatif::HumanBytes;
 use quinn::VarInt;
+use std::cmp::Reverse;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 pub struct LsCommandClient<'a> {
     client: &'a Client,
     remote_path: PathBuf,
+    long: bool,
+    human_readable: bool,
+    sort: LsSort,
 }
 
 impl<'a> LsCommandClient<'a> {
@@ -18,22 +25,31 @@ impl<'a> LsCommandClient<'a> {
         Self {
             client,
             remote_path: remote_path.to_path_buf(),
+            long: false,
+            human_readable: false,
+            sort: LsSort::Name,
         }
     }
 
+    /// How the items are printed and in which order
+    pub fn format(mut self, long: bool, human_readable: bool, sort: LsSort) -> Self {
+        self.long = long;
+        self.human_readable = human_readable;
+        self.sort = sort;
+        self
+    }
+
     async fn do_request(&self, client: &Client, remote_path: &Path) -> Result<()> {
         // build request payload
         let req_payload = LsRequestPayload::new(remote_path);
 
         // do request
         let conn = client.connecting()?.await?;
-        let response = client
-            .request(&conn, MessageType::LsRequest, req_payload)
-            .await?;
+        let res_payload = ls_remote(client, &conn, req_payload).await?;
         conn.close(VarInt::from(200u32), "OK".as_bytes());
 
         // process response
-        if let Some(res_payload) = client.unwrap_message(&response, MessageType::LsResponse)? {
+        if let Some(res_payload) = res_payload {
             self.process_response(res_payload)?;
         }
         println!("done");
@@ -41,22 +57,61 @@ impl<'a> LsCommandClient<'a> {
         Ok(())
     }
 
-    fn process_response(&self, payload: MessagePayloadRef) -> Result<()> {
-        let payload = LsResponsePayload::from_payload(payload)?;
+    fn process_response(&self, mut payload: LsResponsePayload) -> Result<()> {
         println!("ls dir: {:?}", payload.dir);
+        payload.items.sort_by_key(|entry| entry.name());
+        match self.sort {
+            LsSort::Name => {}
+            LsSort::Size => payload.items.sort_by_key(|entry| Reverse(entry.size())),
+            LsSort::Time => payload.items.sort_by_key(|entry| Reverse(entry.mtime())),
+        }
         for entry in payload.items {
-            let entry_type = if entry.is_file() { "file" } else { "dir " };
-            println!("{}: \"{}\"", entry_type, entry.name())
+            let item = if self.long {
+                self.long_format(&entry)
+            } else {
+                format!("{:<5}: \"{}\"", entry.kind(), entry.name())
+            };
+            match entry.link_target() {
+                Some(target) => println!("{item} -> \"{target}\""),
+                None => println!("{item}"),
+            }
         }
         Ok(())
     }
+
+    fn long_format(&self, entry: &DirItem) -> String {
+        let type_char = match (entry.is_dir(), entry.is_file(), entry.is_symlink()) {
+            (true, _, _) => 'd',
+            (_, true, _) => '-',
+            (_, _, true) => 'l',
+            _ => '?',
+        };
+        let size = if self.human_readable {
+            HumanBytes(entry.size()).to_string()
+        } else {
+            entry.size().to_string()
+        };
+        let mtime = Local
+            .timestamp_opt(entry.mtime() as i64, 0)
+            .single()
+            .map_or(entry.mtime().to_string(), |mtime| {
+                mtime.format("%Y-%m-%d %H:%M").to_string()
+            });
+        format!(
+            "{type_char}{} {size:>12} {mtime} \"{}\"",
+            permissions_string(entry.mode()),
+            entry.name()
+        )
+    }
 }
 
 impl<'a> CommandClient for LsCommandClient<'a> {
-    async fn request(&self) {
-        if let Err(e) = self.do_request(self.client, &self.remote_path).await {
-            println!("[ERR][Client] Process request error, error={e}");
-        }
+    async fn request(&self) -> Result<()> {
+        self.do_request(self.client, &self.remote_path)
+            .await
+            .inspect_err(|e| {
+                println!("[ERR][Client] Process request error, error={e}");
+            })
     }
 }
 
@@ -73,32 +128,31 @@ impl CommandServer for LsCommandServer {
         // deserialize request payload
         let payload = LsRequestPayload::from_payload(payload)?;
 
-        // check abs_root_dir and ls_dir relation
-        let mut abs_ls_path = self.0.clone();
-        abs_ls_path.push(payload.remote_path.clone());
-        abs_ls_path = abs_ls_path.canonicalize()?;
+        // check abs_root_dir and ls_dir relation, a link must not lead out of root
+        let abs_ls_path = join_in_root(&self.0, &payload.remote_path)?.canonicalize()?;
         if !abs_ls_path.starts_with(&self.0) {
-            abs_ls_path = self.0.clone();
+            return Err(anyhow!(
+                "ls path resource not exists, path={:?}",
+                payload.remote_path
+            ));
         }
 
         // build response payload
         let res_payload = if abs_ls_path.is_dir() {
             let mut items = Vec::new();
-            for entry in fs::read_dir(abs_ls_path)? {
-                let entry = entry?;
-                let entry_name = entry
-                    .file_name()
-                    .into_string()
-                    .map_err(|e| anyhow!("{e:?}"))?;
-                let entry_type = DirItemType::from(entry.file_type()?);
-                items.push(DirItem::new(entry_name, entry_type));
-            }
-            LsResponsePayload::new(payload.remote_path, items)
+            let mut lister = DirLister {
+                abs_root_dir: &self.0,
+                recursive: payload.recursive,
+                follow_links: payload.follow_links,
+                ancestors: vec![abs_ls_path.clone()],
+            };
+            lister.list(&abs_ls_path, Path::new(""), &mut items)?;
+            LsResponsePayload::new(payload.remote_path, items, true)
         } else if abs_ls_path.is_file() {
             let abs_ls_path = abs_ls_path.to_path_buf();
             let ls_item = abs_ls_path.file_name().unwrap().to_str().unwrap();
-            let item = DirItem::new(ls_item.to_string(), DirItemType::File);
-            LsResponsePayload::new(payload.remote_path, vec![item])
+            let item = DirItem::from_metadata(ls_item.to_string(), &abs_ls_path.metadata()?);
+            LsResponsePayload::new(payload.remote_path, vec![item], false)
         } else {
             return Err(anyhow!(
                 "ls path resource not exists, path={:?}",
@@ -110,3 +164,72 @@ impl CommandServer for LsCommandServer {
         Ok(build_message(MessageType::LsResponse, res_payload))
     }
 }
+
+struct DirLister<'a> {
+    abs_root_dir: &'a Path,
+    recursive: bool,
+    follow_links: bool,
+    ancestors: Vec<PathBuf>,
+}
+
+impl DirLister<'_> {
+    fn list(&mut self, abs_dir: &Path, rel_dir: &Path, items: &mut Vec<DirItem>) -> Result<()> {
+        for entry in fs::read_dir(abs_dir)? {
+            let entry = entry?;
+            let rel_path = rel_dir.join(entry.file_name());
+            let entry_name = rel_path
+                .into_os_string()
+                .into_string()
+                .map_err(|e| anyhow!("{e:?}"))?;
+
+            // a link is listed with its target, unless it is followed inside root
+            // and does not lead back to a dir being walked
+            let mut abs_path = entry.path();
+            let mut meta = entry.metadata()?;
+            if meta.is_symlink() {
+                match self.resolve_link(&abs_path) {
+                    Some(abs_target) => {
+                        meta = abs_target.metadata()?;
+                        abs_path = abs_target;
+                    }
+                    None => {
+                        let target = fs::read_link(&abs_path)?;
+                        items.push(DirItem::from_link(entry_name, &meta, &target));
+                        continue;
+                    }
+                }
+            }
+            items.push(DirItem::from_metadata(entry_name.clone(), &meta));
+
+            if self.recursive && meta.is_dir() {
+                self.ancestors.push(abs_path.canonicalize()?);
+                self.list(&abs_path, Path::new(&entry_name), items)?;
+                self.ancestors.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_link(&self, abs_link_path: &Path) -> Option<PathBuf> {
+        if !self.follow_links {
+            return None;
+        }
+        abs_link_path.canonicalize().ok().filter(|abs_target| {
+            abs_target.starts_with(self.abs_root_dir) && !self.ancestors.contains(abs_target)
+        })
+    }
+}
+
+pub async fn ls_remote(
+    client: &Client,
+    conn: &quinn::Connection,
+    req_payload: LsRequestPayload,
+) -> Result<Option<LsResponsePayload>> {
+    let response = client
+        .request(conn, MessageType::LsRequest, req_payload)
+        .await?;
+    match client.unwrap_message(&response, MessageType::LsResponse)? {
+        Some(res_payload) => Ok(Some(LsResponsePayload::from_payload(res_payload)?)),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,120 @@
+use crate::command::{CommandClient, CommandServer};
+use crate::message::mkdir::{MkdirRequestPayload, MkdirResponsePayload};
+use crate::message::*;
+use crate::quic::client::Client;
+use crate::utils::dir::{join_in_root, make_dirs_in_root};
+use anyhow::{anyhow, Result};
+use path_absolutize::Absolutize;
+use quinn::VarInt;
+use std::path::{Path, PathBuf};
+
+/// Makes a remote dir with its missing parents, an existing dir is kept
+pub async fn mkdir_remote(
+    client: &Client,
+    conn: &quinn::Connection,
+    remote_path: &Path,
+) -> Result<()> {
+    let response = client
+        .request(
+            conn,
+            MessageType::MkdirRequest,
+            MkdirRequestPayload::new(remote_path, true),
+        )
+        .await?;
+    client
+        .unwrap_message(&response, MessageType::MkdirResponse)?
+        .ok_or(anyhow!("make remote dir failed, path={remote_path:?}"))?;
+    Ok(())
+}
+
+pub struct MkdirCommandClient<'a> {
+    client: &'a Client,
+    remote_path: PathBuf,
+    parents: bool,
+}
+
+impl<'a> MkdirCommandClient<'a> {
+    pub fn new(client: &'a Client, remote_path: &Path, parents: bool) -> Self {
+        Self {
+            client,
+            remote_path: remote_path.to_path_buf(),
+            parents,
+        }
+    }
+
+    async fn do_request(&self) -> Result<()> {
+        // do request
+        let conn = self.client.connecting()?.await?;
+        let response = self
+            .client
+            .request(
+                &conn,
+                MessageType::MkdirRequest,
+                MkdirRequestPayload::new(&self.remote_path, self.parents),
+            )
+            .await?;
+        conn.close(VarInt::from(200u32), "OK".as_bytes());
+
+        // process response
+        let res_payload = self
+            .client
+            .unwrap_message(&response, MessageType::MkdirResponse)?
+            .ok_or(anyhow!(
+                "make remote dir failed, path={:?}",
+                self.remote_path
+            ))?;
+        let res_payload = MkdirResponsePayload::from_payload(res_payload)?;
+        println!("mkdir: {:?}", res_payload.remote_path);
+        println!("done");
+
+        Ok(())
+    }
+}
+
+impl<'a> CommandClient for MkdirCommandClient<'a> {
+    async fn request(&self) -> Result<()> {
+        self.do_request().await.inspect_err(|e| {
+            println!("[ERR][Client] Process request error, error={e}");
+        })
+    }
+}
+
+pub struct MkdirCommandServer(PathBuf);
+
+impl MkdirCommandServer {
+    pub fn new(abs_root_dir: PathBuf) -> Self {
+        Self(abs_root_dir)
+    }
+}
+
+impl CommandServer for MkdirCommandServer {
+    async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage> {
+        // deserialize request payload
+        let payload = MkdirRequestPayload::from_payload(payload)?;
+
+        // check path valid, the root itself always exists
+        if !self
+            .0
+            .join(&payload.remote_path)
+            .absolutize()?
+            .starts_with(&self.0)
+        {
+            return Err(anyhow!("path outside root, path={:?}", payload.remote_path));
+        }
+        let abs_path = join_in_root(&self.0, &payload.remote_path)?;
+        if abs_path == self.0 && !payload.parents {
+            return Err(anyhow!(
+                "dir already exists, path={:?}",
+                payload.remote_path
+            ));
+        }
+
+        // make the dir
+        make_dirs_in_root(&self.0, &abs_path, payload.parents)
+            .map_err(|e| anyhow!("{e}, path={:?}", payload.remote_path))?;
+
+        // build response message
+        let res_payload = MkdirResponsePayload::new(payload.remote_path);
+        Ok(build_message(MessageType::MkdirResponse, res_payload))
+    }
+}
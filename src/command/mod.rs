@@ -1,14 +1,31 @@
-use crate::message::{MessagePayloadRef, SendMessage};
+use crate::message::{MessagePayloadRef, MessagePayloadSize, SendMessage};
 use anyhow::Result;
 
+pub mod delta;
+pub mod digest;
 pub mod get;
 pub mod ls;
+pub mod mkdir;
+pub mod mv;
 pub mod put;
+pub mod rm;
+pub mod stat;
+pub mod sync;
 
 pub trait CommandClient {
-    async fn request(&self);
+    /// The error is logged already, it is returned for the exit status
+    async fn request(&self) -> Result<()>;
 }
 
 pub trait CommandServer {
     async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage>;
 }
+
+pub trait StreamCommandServer {
+    async fn handle_stream(
+        &self,
+        payload_size: MessagePayloadSize,
+        rs: &mut quinn::RecvStream,
+        ss: &mut quinn::SendStream,
+    ) -> Result<()>;
+}
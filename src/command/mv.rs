@@ -0,0 +1,149 @@
+use crate::cli::MvArgs;
+use crate::command::{CommandClient, CommandServer};
+use crate::message::mv::{MvRequestPayload, MvResponsePayload};
+use crate::message::*;
+use crate::quic::client::Client;
+use crate::utils::dir::{entry_in_root, unique_file_path};
+use crate::utils::file::ExistingFilePolicy;
+use anyhow::{anyhow, Result};
+use quinn::VarInt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct MvCommandClient<'a> {
+    client: &'a Client,
+    src_path: PathBuf,
+    dst_path: PathBuf,
+    options: MvArgs,
+}
+
+impl<'a> MvCommandClient<'a> {
+    pub fn new(client: &'a Client, src_path: &Path, dst_path: &Path, options: MvArgs) -> Self {
+        Self {
+            client,
+            src_path: src_path.to_path_buf(),
+            dst_path: dst_path.to_path_buf(),
+            options,
+        }
+    }
+
+    async fn do_request(&self) -> Result<()> {
+        // do request
+        let conn = self.client.connecting()?.await?;
+        let response = self
+            .client
+            .request(
+                &conn,
+                MessageType::MvRequest,
+                MvRequestPayload::new(
+                    &self.src_path,
+                    &self.dst_path,
+                    self.options.existing_file_policy(),
+                ),
+            )
+            .await?;
+        conn.close(VarInt::from(200u32), "OK".as_bytes());
+
+        // process response
+        let res_payload = self
+            .client
+            .unwrap_message(&response, MessageType::MvResponse)?
+            .ok_or(anyhow!("move remote path failed, path={:?}", self.src_path))?;
+        let res_payload = MvResponsePayload::from_payload(res_payload)?;
+        if res_payload.skipped {
+            println!("skip existing path: {:?}", res_payload.dst_path);
+        } else {
+            println!("mv: {:?} -> {:?}", self.src_path, res_payload.dst_path);
+        }
+        println!("done");
+
+        Ok(())
+    }
+}
+
+impl<'a> CommandClient for MvCommandClient<'a> {
+    async fn request(&self) -> Result<()> {
+        self.do_request().await.inspect_err(|e| {
+            println!("[ERR][Client] Process request error, error={e}");
+        })
+    }
+}
+
+pub struct MvCommandServer(PathBuf);
+
+impl MvCommandServer {
+    pub fn new(abs_root_dir: PathBuf) -> Self {
+        Self(abs_root_dir)
+    }
+}
+
+impl CommandServer for MvCommandServer {
+    async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage> {
+        // deserialize request payload
+        let payload = MvRequestPayload::from_payload(payload)?;
+
+        // check source valid, the root itself is never moved
+        let abs_src_path = entry_in_root(&self.0, &payload.src_path)?;
+        if abs_src_path == self.0 {
+            return Err(anyhow!(
+                "refuse to move root dir, path={:?}",
+                payload.src_path
+            ));
+        }
+        let src_is_dir = fs::symlink_metadata(&abs_src_path)?.is_dir();
+
+        // an existing dir takes the source into it
+        let mut abs_dst_path = entry_in_root(&self.0, &payload.dst_path)?;
+        if abs_dst_path.is_dir() {
+            abs_dst_path = abs_dst_path.canonicalize()?;
+            if !abs_dst_path.starts_with(&self.0) {
+                return Err(anyhow!("path outside root, path={:?}", payload.dst_path));
+            }
+            let src_name = abs_src_path
+                .file_name()
+                .ok_or(anyhow!("got source name error"))?;
+            abs_dst_path.push(src_name);
+        }
+        if abs_dst_path == abs_src_path {
+            return Err(anyhow!("source and destination are the same path"));
+        }
+        if src_is_dir && abs_dst_path.starts_with(&abs_src_path) {
+            return Err(anyhow!("refuse to move a dir into itself"));
+        }
+
+        // apply the policy for an existing destination
+        if let Ok(dst_meta) = fs::symlink_metadata(&abs_dst_path) {
+            match payload.if_exists {
+                ExistingFilePolicy::Overwrite => {
+                    if dst_meta.is_dir() != src_is_dir {
+                        return Err(anyhow!(
+                            "destination exists as another type, path={:?}",
+                            payload.dst_path
+                        ));
+                    }
+                    if dst_meta.is_dir() {
+                        fs::remove_dir_all(&abs_dst_path)?;
+                    }
+                }
+                ExistingFilePolicy::SkipExisting => {
+                    let res_payload = MvResponsePayload::skip(abs_dst_path.strip_prefix(&self.0)?);
+                    return Ok(build_message(MessageType::MvResponse, res_payload));
+                }
+                ExistingFilePolicy::Rename => abs_dst_path = unique_file_path(&abs_dst_path),
+                ExistingFilePolicy::Resume | ExistingFilePolicy::FailIfExists => {
+                    return Err(anyhow!(
+                        "destination already exists, path={:?}",
+                        payload.dst_path
+                    ));
+                }
+            }
+        }
+
+        // move it
+        fs::rename(&abs_src_path, &abs_dst_path)?;
+
+        // build response message
+        let res_payload = MvResponsePayload::new(abs_dst_path.strip_prefix(&self.0)?);
+        Ok(build_message(MessageType::MvResponse, res_payload))
+    }
+}
@@ -1,173 +1,651 @@
-use crate::command::{CommandClient, CommandServer};
+use crate::cli::{LinkPolicy, TransferArgs};
+use crate::command::delta::put_delta;
+use crate::command::digest::{check_remote_digest, remote_file_digest, remote_prefix_matches};
+use crate::command::mkdir::mkdir_remote;
+use crate::command::{CommandClient, StreamCommandServer};
 use crate::message::put::*;
 use crate::message::*;
-use crate::quic::client::{Client, Stage};
-use crate::utils::file::{buffer_size, index_offset, FileChunkSize};
+use crate::quic::client::Client;
+use crate::utils::codec::ChunkCodec;
+use crate::utils::dir::{
+    is_single_component, join_in_root, link_in_root, make_dirs_in_root, resolve_in_root,
+    unique_file_path, walk_files,
+};
+use crate::utils::file::{
+    add_part_range, buffer_size, get_file_chunk_size, index_offset, negotiate_chunk_unit_size,
+    part_done_len, part_file_path, remove_part_state, ChunkError, ExistingFilePolicy, FileAttrs,
+    FileChunkSize, MAX_CHUNK_RETRIES, STDIO_PATH,
+};
+use crate::utils::progress::{is_interactive, Progress};
+use crate::utils::rate::RateLimiter;
+use crate::utils::stream::{read_request_payload, EncodedChunk, FileRange};
 use anyhow::{anyhow, Result};
-use bytes::Bytes;
-use chrono::Local;
+use futures::{stream, StreamExt, TryStreamExt};
+use path_absolutize::Absolutize;
 use quinn::VarInt;
+use std::cell::RefCell;
+use std::fs;
 use std::fs::File;
-use std::os::unix::fs::FileExt;
+use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
 
 pub struct PutCommandClient<'a> {
     client: &'a Client,
     file: PathBuf,
     remote_dir: PathBuf,
+    remote_name: Option<String>,
+    options: TransferArgs,
+    limiter: Option<RateLimiter>,
+    progress: Progress,
 }
 
 impl<'a> PutCommandClient<'a> {
-    pub fn new(client: &'a Client, file: &Path, remote_dir: &Path) -> Self {
+    pub fn new(client: &'a Client, file: &Path, remote_dir: &Path, options: TransferArgs) -> Self {
         Self {
             client,
             file: file.to_path_buf(),
             remote_dir: remote_dir.to_path_buf(),
+            remote_name: None,
+            limiter: options.rate_limiter(),
+            progress: Progress::new(),
+            options,
         }
     }
 
+    /// The name a file read from stdin is stored as
+    pub fn remote_name(mut self, remote_name: Option<String>) -> Self {
+        self.remote_name = remote_name;
+        self
+    }
+
     async fn do_request(&self) -> Result<()> {
-        println!(
-            "put file: {:?}, to remote dir: {:?}, time:{}",
+        self.log(format!(
+            "put file: {:?}, to remote dir: {:?}",
+            self.file, self.remote_dir
+        ));
+
+        let conn = self.client.connecting()?.await?;
+        if self.file == Path::new(STDIO_PATH) {
+            let remote_name = self
+                .remote_name
+                .as_deref()
+                .ok_or(anyhow!("remote name required for stdin"))?;
+            self.put_stdin(&conn, remote_name).await?;
+        } else if self.file.is_dir() {
+            let dir_name = self
+                .file
+                .absolutize()?
+                .file_name()
+                .ok_or(anyhow!("got dir name error"))?
+                .to_os_string();
+            // dirs are made as they are walked, so an empty one is put as well
+            let remote_root_dir = self.remote_dir.join(&dir_name);
+            mkdir_remote(self.client, &conn, &remote_root_dir).await?;
+            let mut links = Vec::new();
+            for rel_file_path in walk_files(&self.file, self.options.links)? {
+                let mut remote_dir = remote_root_dir.clone();
+                if let Some(rel_dir) = rel_file_path.parent() {
+                    remote_dir.push(rel_dir);
+                }
+                let local_file_path = self.file.join(&rel_file_path);
+                if self.options.links == LinkPolicy::CopyAsLink && local_file_path.is_symlink() {
+                    links.push((local_file_path, remote_dir));
+                } else if local_file_path.is_dir() {
+                    mkdir_remote(self.client, &conn, &remote_root_dir.join(&rel_file_path)).await?;
+                } else {
+                    self.put_file(&conn, &local_file_path, &remote_dir).await?;
+                }
+            }
+
+            // links are made last, the server refuses one whose target is not there yet
+            for (local_link_path, remote_dir) in links {
+                self.put_link(&conn, &local_link_path, &remote_dir).await?;
+            }
+        } else {
+            self.put_file(&conn, &self.file, &self.remote_dir).await?;
+        }
+        conn.close(VarInt::from(200u32), "OK".as_bytes());
+
+        self.log(format!(
+            "put file: {:?}, to remote dir: {:?} finish, {}",
             self.file,
             self.remote_dir,
-            Local::now().timestamp_millis()
-        );
+            self.progress.summary()
+        ));
 
-        let file_name = self
-            .file
+        Ok(())
+    }
+
+    pub async fn put_file(
+        &self,
+        conn: &quinn::Connection,
+        local_file_path: &Path,
+        remote_dir: &Path,
+    ) -> Result<()> {
+        let file_name = local_file_path
             .file_name()
             .ok_or(anyhow!("got file name error"))?;
-        let mut req_meta = PutRequestMeta::new(file_name, &self.remote_dir);
-        let mut req_payload = PutRequestPayload::new(req_meta.clone(), None);
+        let mut remote_file_path = remote_dir.join(file_name);
+        let mut req_meta = PutRequestMeta::new(
+            file_name,
+            remote_dir,
+            self.options.chunk_size,
+            ChunkCodec::preferred(self.options.compress),
+        );
 
-        let conn = self.client.connecting()?.await?;
-        loop {
-            // do request
-            println!(">>>: {:?}", req_payload.meta);
-            let response = self
-                .client
-                .request(&conn, MessageType::PutRequest, req_payload)
-                .await?;
+        // open the remote file by the policy for an existing one
+        req_meta.if_exists = self.options.existing_file_policy();
+        let res_payload = self.send_request(conn, req_meta.clone(), None).await?;
+        if res_payload.skipped {
+            self.log(format!("skip existing file: {remote_file_path:?}"));
+            return Ok(());
+        }
+        let renamed = res_payload
+            .file_name
+            .filter(|name| *name != req_meta.file_name);
+        if let Some(remote_file_name) = renamed {
+            remote_file_path = remote_dir.join(&remote_file_name);
+            self.log(format!("remote file exists, put as: {remote_file_path:?}"));
+            req_meta.file_name = remote_file_name;
+        }
+        req_meta.if_exists = ExistingFilePolicy::Resume;
 
-            // process response
-            if let Some(res_payload) = self
-                .client
-                .unwrap_message(&response, MessageType::PutResponse)?
+        // resume handshake, restart when the remote data differs from local
+        let mut remote_file_chunk_size = res_payload.remote_file_chunk_size;
+        let remote_file_len = remote_file_chunk_size.total_size() as u64;
+        let local_file_len = fs::metadata(local_file_path)?.len();
+        let restart = if !res_payload.is_part {
+            // a complete remote file is only replaced when it differs from local
+            if remote_file_len == local_file_len
+                && remote_prefix_matches(
+                    self.client,
+                    conn,
+                    local_file_path,
+                    &remote_file_path,
+                    remote_file_len,
+                )
+                .await?
             {
-                let stage = self.process_response(res_payload)?;
-                match stage {
-                    Stage::Processing((is_done, curr_tarns_trunk_index, data)) => {
-                        req_meta.curr_trans_trunk_index = curr_tarns_trunk_index;
-                        req_meta.is_done = is_done;
-                        req_payload = PutRequestPayload::new(req_meta.clone(), data);
-                    }
-                    Stage::Finish => break,
-                }
-            } else {
-                break;
+                self.log(format!("remote file is up to date: {remote_file_path:?}"));
+                return self.finish(conn, req_meta, local_file_path).await;
             }
+            if self.options.delta && remote_file_len > 0 {
+                self.log(format!(
+                    "remote data mismatch, send delta: {remote_file_path:?}"
+                ));
+                put_delta(
+                    self.client,
+                    conn,
+                    local_file_path,
+                    remote_dir,
+                    self.limiter.as_ref(),
+                    |message| self.log(message),
+                )
+                .await?;
+                self.finish(conn, req_meta, local_file_path).await?;
+                return check_remote_digest(
+                    self.client,
+                    conn,
+                    local_file_path,
+                    &remote_file_path,
+                    |message| self.log(message),
+                )
+                .await;
+            }
+            true
+        } else {
+            remote_file_len > 0
+                && !remote_prefix_matches(
+                    self.client,
+                    conn,
+                    local_file_path,
+                    &part_file_path(&remote_file_path)?,
+                    remote_file_len,
+                )
+                .await?
+        };
+        if restart {
+            self.log(format!(
+                "remote data mismatch, restart: {remote_file_path:?}"
+            ));
+            req_meta.restart = true;
+            remote_file_chunk_size = self
+                .send_request(conn, req_meta.clone(), None)
+                .await?
+                .remote_file_chunk_size;
+            req_meta.restart = false;
         }
-        conn.close(VarInt::from(200u32), "OK".as_bytes());
 
-        println!(
-            "put file: {:?}, to remote dir: {:?} finish, time:{:?}",
-            self.file,
-            self.remote_dir,
-            Local::now().timestamp_millis()
+        // the server may cap the chunk size, and an old server only takes raw data
+        let chunk_unit_size = remote_file_chunk_size.chunk_unit_size();
+        req_meta.chunk_unit_size = chunk_unit_size;
+        req_meta.codec = res_payload.codec;
+        let sparse = res_payload.sparse;
+
+        // transfer the missing chunks in parallel
+        let local_file = File::open(local_file_path)?;
+        let local_file_chunk_size =
+            FileChunkSize::from_len(local_file_len as usize, chunk_unit_size);
+        let start_index = remote_file_chunk_size.integer_chunks();
+        self.progress.start_file(
+            &file_name.to_string_lossy(),
+            Some(local_file_len),
+            index_offset(start_index, chunk_unit_size),
+        )?;
+        if local_file_chunk_size != remote_file_chunk_size {
+            stream::iter(start_index..local_file_chunk_size.total_chunks())
+                .map(|index| {
+                    self.put_chunk(conn, &local_file, local_file_len, &req_meta, sparse, index)
+                })
+                .buffer_unordered(self.options.parallel as usize)
+                .try_collect::<Vec<_>>()
+                .await?;
+        }
+        self.progress.finish_file();
+
+        // finish
+        self.finish(conn, req_meta, local_file_path).await?;
+
+        // verify whole file
+        check_remote_digest(
+            self.client,
+            conn,
+            local_file_path,
+            &remote_file_path,
+            |message| self.log(message),
+        )
+        .await
+    }
+
+    /// Recreates a local link on the server, which refuses a target outside root
+    async fn put_link(
+        &self,
+        conn: &quinn::Connection,
+        local_link_path: &Path,
+        remote_dir: &Path,
+    ) -> Result<()> {
+        let link_name = local_link_path
+            .file_name()
+            .ok_or(anyhow!("got link name error"))?;
+        let mut req_meta = PutRequestMeta::new(
+            link_name,
+            remote_dir,
+            self.options.chunk_size,
+            ChunkCodec::Raw,
         );
+        req_meta.if_exists = self.options.existing_file_policy();
+        req_meta.link_target = Some(fs::read_link(local_link_path)?);
+        match self.send_request(conn, req_meta, None).await {
+            Ok(res_payload) if res_payload.skipped => {
+                self.log(format!(
+                    "skip existing file: {:?}",
+                    remote_dir.join(link_name)
+                ));
+            }
+            Ok(_) => {}
+            // a refused link leaves the rest of the dir to transfer
+            Err(e) => self.log(format!("[WARN]skip link: {local_link_path:?}, error={e}")),
+        }
+        Ok(())
+    }
+
+    /// The remote file takes its final name, and the attrs of the local file
+    async fn finish(
+        &self,
+        conn: &quinn::Connection,
+        mut req_meta: PutRequestMeta,
+        local_file_path: &Path,
+    ) -> Result<()> {
+        req_meta.is_done = true;
+        if !self.options.no_preserve {
+            req_meta.attrs = Some(FileAttrs::from_metadata(&fs::metadata(local_file_path)?));
+        }
+        self.send_request(conn, req_meta, None).await?;
+        Ok(())
+    }
+
+    async fn put_chunk(
+        &self,
+        conn: &quinn::Connection,
+        local_file: &File,
+        local_file_len: u64,
+        req_meta: &PutRequestMeta,
+        sparse: bool,
+        index: u64,
+    ) -> Result<()> {
+        // locate chunk
+        let offset = index_offset(index, req_meta.chunk_unit_size);
+        let chunk_size =
+            buffer_size((local_file_len - offset) as usize, req_meta.chunk_unit_size) as u64;
+        let chunk = FileRange::new(local_file, offset, chunk_size);
+        let data = EncodedChunk::new(&chunk, req_meta.codec, sparse)?;
 
+        let mut req_meta = req_meta.clone();
+        req_meta.curr_trans_trunk_index = index;
+        req_meta.chunk_checksum = chunk.checksum()?;
+        self.send_chunk(conn, req_meta, &data).await?;
+        self.progress.inc(chunk_size);
         Ok(())
     }
 
-    fn process_response(
+    async fn send_chunk(
         &self,
-        payload: MessagePayloadRef,
-    ) -> Result<Stage<(bool, u64, Option<Bytes>)>> {
-        let local_file = File::open(&self.file)?;
-        let local_file_len = local_file.metadata()?.len() as usize;
-        let local_file_chunk_size = FileChunkSize::from(local_file_len);
-
-        // get payload
-        let payload = PutResponsePayload::from_payload(payload)?;
-        println!("<<<: {payload:?}");
-
-        if payload.is_done {
-            return Ok(Stage::Finish);
-        }
-        let stage_data = if local_file_chunk_size != payload.remote_file_chunk_size {
-            let mut curr_tarns_trunk_index = payload.remote_file_chunk_size.total_chunks();
-            if payload.remote_file_chunk_size.rest_size() != 0 {
-                curr_tarns_trunk_index -= 1;
+        conn: &quinn::Connection,
+        mut req_meta: PutRequestMeta,
+        data: &EncodedChunk<'_>,
+    ) -> Result<()> {
+        req_meta.codec = data.codec();
+        let index = req_meta.curr_trans_trunk_index;
+        let mut retries = 0;
+        loop {
+            let payload = self
+                .send_request(conn, req_meta.clone(), Some(data))
+                .await?;
+            if !payload.chunk_mismatch {
+                return Ok(());
             }
-            let offset = index_offset(curr_tarns_trunk_index);
-            let buffer_size = buffer_size(local_file_len - offset as usize);
-            let mut buffer = vec![0; buffer_size];
-            let _ = local_file.read_at(&mut buffer, offset)?;
-            (false, curr_tarns_trunk_index, Some(Bytes::from(buffer)))
-        } else {
-            (true, 0, None)
-        };
 
-        Ok(Stage::Processing(stage_data))
+            // resend the same chunk
+            let e = ChunkError::ChecksumMismatch(index);
+            if retries >= MAX_CHUNK_RETRIES {
+                return Err(e.into());
+            }
+            retries += 1;
+            self.log(format!("[WARN]{e}, retry={retries}"));
+        }
+    }
+
+    /// Stdin is read once in order, so nothing is resumed and the size is only known at its end
+    async fn put_stdin(&self, conn: &quinn::Connection, remote_name: &str) -> Result<()> {
+        let mut remote_file_path = self.remote_dir.join(remote_name);
+        let mut req_meta = PutRequestMeta::new(
+            remote_name,
+            &self.remote_dir,
+            self.options.chunk_size,
+            ChunkCodec::preferred(self.options.compress),
+        );
+
+        // open the remote file by the policy for an existing one, always from the start
+        req_meta.if_exists = self.options.existing_file_policy();
+        req_meta.restart = true;
+        let res_payload = self.send_request(conn, req_meta.clone(), None).await?;
+        if res_payload.skipped {
+            self.log(format!("skip existing file: {remote_file_path:?}"));
+            return Ok(());
+        }
+        let renamed = res_payload
+            .file_name
+            .filter(|name| *name != req_meta.file_name);
+        if let Some(remote_file_name) = renamed {
+            remote_file_path = self.remote_dir.join(&remote_file_name);
+            self.log(format!("remote file exists, put as: {remote_file_path:?}"));
+            req_meta.file_name = remote_file_name;
+        }
+        req_meta.if_exists = ExistingFilePolicy::Resume;
+        req_meta.restart = false;
+        req_meta.chunk_unit_size = res_payload.remote_file_chunk_size.chunk_unit_size();
+        req_meta.codec = res_payload.codec;
+
+        // read the chunks in order, and put them in parallel
+        self.progress.start_file(remote_name, None, 0)?;
+        let hasher = RefCell::new(blake3::Hasher::new());
+        let chunk_unit_size = req_meta.chunk_unit_size;
+        stream::try_unfold((tokio::io::stdin(), 0), |(mut stdin, index)| {
+            let hasher = &hasher;
+            async move {
+                let mut data = Vec::with_capacity(chunk_unit_size);
+                (&mut stdin)
+                    .take(chunk_unit_size as u64)
+                    .read_to_end(&mut data)
+                    .await?;
+                if data.is_empty() {
+                    return Ok(None);
+                }
+                hasher.borrow_mut().update(&data);
+                Ok::<_, anyhow::Error>(Some(((index, data), (stdin, index + 1))))
+            }
+        })
+        .map_ok(|(index, data)| {
+            let mut req_meta = req_meta.clone();
+            req_meta.curr_trans_trunk_index = index;
+            req_meta.chunk_checksum = crc32fast::hash(&data);
+            async move {
+                let data_len = data.len() as u64;
+                let data = EncodedChunk::from_bytes(data, req_meta.codec)?;
+                self.send_chunk(conn, req_meta, &data).await?;
+                self.progress.inc(data_len);
+                Ok::<_, anyhow::Error>(())
+            }
+        })
+        .try_buffer_unordered(self.options.parallel as usize)
+        .try_collect::<Vec<_>>()
+        .await?;
+        self.progress.finish_file();
+
+        // finish, then verify whole file
+        req_meta.is_done = true;
+        self.send_request(conn, req_meta, None).await?;
+        let local_digest = hasher.into_inner().finalize().to_hex().to_string();
+        let remote_digest = remote_file_digest(self.client, conn, &remote_file_path).await?;
+        if local_digest != remote_digest {
+            return Err(anyhow!(
+                "file digest mismatch, local=stdin({local_digest}), remote={remote_file_path:?}({remote_digest})"
+            ));
+        }
+        self.log(format!("digest ok: stdin, {local_digest}"));
+        Ok(())
+    }
+
+    async fn send_request(
+        &self,
+        conn: &quinn::Connection,
+        req_meta: PutRequestMeta,
+        data: Option<&EncodedChunk<'_>>,
+    ) -> Result<PutResponsePayload> {
+        // do request, the chunk data is streamed after the meta
+        if !is_interactive() {
+            println!(">>>: {req_meta:?}");
+        }
+        let file_name = req_meta.file_name.clone();
+        let data_size = data.map_or(0, |data| data.size());
+        let (mut ss, mut rs) = self
+            .client
+            .request_stream(
+                conn,
+                MessageType::PutRequest,
+                PutRequestPayload::new(req_meta),
+                data_size,
+            )
+            .await?;
+        if let Some(data) = data {
+            data.send_to(&mut ss, self.limiter.as_ref()).await?;
+        }
+        ss.finish()?;
+
+        // process response
+        let response = self.client.recv_message(&mut rs).await?;
+        let res_payload = self
+            .client
+            .unwrap_message(&response, MessageType::PutResponse)?
+            .ok_or(anyhow!("put file failed, file={file_name:?}"))?;
+        let payload = PutResponsePayload::from_payload(res_payload)?;
+        if !is_interactive() {
+            println!("<<<: {payload:?}");
+        }
+        Ok(payload)
+    }
+
+    fn log(&self, message: String) {
+        self.progress.println(&message);
     }
 }
 
 impl<'a> CommandClient for PutCommandClient<'a> {
-    async fn request(&self) {
-        if let Err(e) = self.do_request().await {
-            println!("[ERR][Client] Process request error, error={e}");
-        }
+    async fn request(&self) -> Result<()> {
+        self.do_request().await.inspect_err(|e| {
+            self.log(format!("[ERR][Client] Process request error, error={e}"));
+        })
     }
 }
 
-pub struct PutCommandServer(PathBuf);
+pub struct PutCommandServer(PathBuf, usize, Option<RateLimiter>);
 
 impl PutCommandServer {
-    pub fn new(abs_root_dir: PathBuf) -> Self {
-        Self(abs_root_dir)
+    pub fn new(
+        abs_root_dir: PathBuf,
+        max_chunk_unit_size: usize,
+        limiter: Option<RateLimiter>,
+    ) -> Self {
+        Self(abs_root_dir, max_chunk_unit_size, limiter)
     }
 }
 
-impl CommandServer for PutCommandServer {
-    async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage> {
-        // deserialize request payload
-        let payload = PutRequestPayloadRef::from_payload(payload)?;
-
-        if payload.meta.is_done {
-            return Ok(build_message(
-                MessageType::PutResponse,
-                PutResponsePayload::finish(),
+impl PutCommandServer {
+    async fn store(
+        &self,
+        meta: PutRequestMeta,
+        data_size: u64,
+        rs: &mut quinn::RecvStream,
+    ) -> Result<PutResponsePayload> {
+        // check chunk size valid
+        let chunk_unit_size = negotiate_chunk_unit_size(meta.chunk_unit_size, self.1);
+        if data_size > 0 && (meta.chunk_unit_size > self.1 || data_size > chunk_unit_size as u64) {
+            return Err(anyhow!(
+                "chunk size exceeds the limit, size={data_size}, limit={chunk_unit_size}"
             ));
         }
 
-        // build file path
-        let mut remote_file_path = self.0.clone();
-        remote_file_path.push(payload.meta.remote_dir.clone());
-        remote_file_path = remote_file_path.canonicalize()?;
-        if !remote_file_path.starts_with(&self.0) {
-            remote_file_path = self.0.clone();
+        // check file name valid
+        if !is_single_component(&meta.file_name) {
+            return Err(anyhow!("file name invalid, name={:?}", meta.file_name));
+        }
+
+        // build file path, create missing dirs inside root
+        let remote_dir = join_in_root(&self.0, &meta.remote_dir)?;
+        let mut remote_file_path = make_dirs_in_root(&self.0, &remote_dir, true)
+            .map_err(|e| anyhow!("{e}, remote dir={:?}", meta.remote_dir))?;
+        remote_file_path.push(meta.file_name.clone());
+
+        // apply the policy for an existing file, the first request only carries it
+        let mut restart = meta.restart;
+        if fs::symlink_metadata(&remote_file_path).is_ok() {
+            match meta.if_exists {
+                ExistingFilePolicy::Resume => {}
+                ExistingFilePolicy::Overwrite => restart = true,
+                ExistingFilePolicy::SkipExisting => return Ok(PutResponsePayload::skip()),
+                ExistingFilePolicy::Rename => {
+                    remote_file_path = unique_file_path(&remote_file_path)
+                }
+                ExistingFilePolicy::FailIfExists => {
+                    return Err(anyhow!("file already exists, name={:?}", meta.file_name));
+                }
+            }
+        }
+
+        // a link replaces what is there, but never points outside root
+        if let Some(link_target) = &meta.link_target {
+            if !link_in_root(&self.0, &remote_file_path, link_target) {
+                return Err(anyhow!("link target outside root, target={link_target:?}"));
+            }
+            if fs::symlink_metadata(&remote_file_path).is_ok() {
+                fs::remove_file(&remote_file_path)?;
+            }
+            symlink(link_target, &remote_file_path)?;
+            let mut res_payload = PutResponsePayload::finish();
+            res_payload.file_name = remote_file_path.file_name().map(PathBuf::from);
+            return Ok(res_payload);
+        }
+
+        // the final file is replaced by the part file only after the last chunk is verified
+        let part_file_path = part_file_path(&remote_file_path)?;
+        resolve_in_root(&self.0, &remote_file_path)?;
+        resolve_in_root(&self.0, &part_file_path)?;
+        if meta.is_done {
+            if part_file_path.exists() {
+                fs::rename(&part_file_path, &remote_file_path)?;
+            }
+            remove_part_state(&part_file_path)?;
+            // the mode comes from the client, special bits are not honoured
+            if let Some(attrs) = meta.attrs {
+                attrs.permission_only().apply(&remote_file_path)?;
+            }
+            return Ok(PutResponsePayload::finish());
+        }
+
+        // report a complete file, it is kept until the client decides to replace it
+        if data_size == 0 && !restart && !part_file_path.exists() && remote_file_path.exists() {
+            let remote_file_chunk_size = get_file_chunk_size(&remote_file_path, chunk_unit_size);
+            let mut res_payload = PutResponsePayload::new(remote_file_chunk_size, meta.codec);
+            res_payload.file_name = remote_file_path.file_name().map(PathBuf::from);
+            res_payload.is_part = false;
+            return Ok(res_payload);
         }
-        remote_file_path.push(payload.meta.file_name.clone());
 
-        // open & create file
+        // open & create the part file, the chunks written into it are kept beside it
         let remote_file = File::options()
             .create(true)
-            .append(true)
-            .open(remote_file_path)?;
+            .truncate(false)
+            .write(true)
+            .open(&part_file_path)?;
+
+        // drop stale data
+        if restart {
+            remote_file.set_len(0)?;
+            remove_part_state(&part_file_path)?;
+        }
 
         // store data
-        if !payload.data.is_empty() {
-            // append data
-            let offset = index_offset(payload.meta.curr_trans_trunk_index);
-            remote_file.write_all_at(payload.data, offset)?;
+        if data_size > 0 {
+            // stream data into the part file, a mismatched chunk is rewritten by the resend
+            let offset = index_offset(meta.curr_trans_trunk_index, chunk_unit_size);
+            let chunk = FileRange::new(&remote_file, offset, chunk_unit_size as u64);
+            if chunk
+                .recv_encoded_from(rs, meta.codec, data_size, self.2.as_ref())
+                .await?
+                != meta.chunk_checksum
+            {
+                let remote_file_chunk_size = FileChunkSize::from_len(
+                    part_done_len(&part_file_path)? as usize,
+                    chunk_unit_size,
+                );
+                return Ok(PutResponsePayload::mismatch(remote_file_chunk_size));
+            }
+            add_part_range(&part_file_path, offset, chunk_unit_size as u64)?;
         }
 
-        // build response payload
-        let remote_file_chunk_size = FileChunkSize::from(remote_file.metadata()?.len() as usize);
-        let res_payload = PutResponsePayload::new(remote_file_chunk_size);
+        // build response payload, the resume point is the end of the data written without a gap
+        let remote_file_chunk_size =
+            FileChunkSize::from_len(part_done_len(&part_file_path)? as usize, chunk_unit_size);
+        let mut res_payload = PutResponsePayload::new(remote_file_chunk_size, meta.codec);
+        res_payload.file_name = remote_file_path.file_name().map(PathBuf::from);
+        Ok(res_payload)
+    }
+}
 
-        // build response message
-        Ok(build_message(MessageType::PutResponse, res_payload))
+impl StreamCommandServer for PutCommandServer {
+    async fn handle_stream(
+        &self,
+        payload_size: MessagePayloadSize,
+        rs: &mut quinn::RecvStream,
+        ss: &mut quinn::SendStream,
+    ) -> Result<()> {
+        // receive request meta
+        let mut meta_size_bytes = [0; SIZE_OF_PUT_META_SIZE];
+        rs.read_exact(&mut meta_size_bytes).await?;
+        let meta_size = PutRequestMeta::meta_size(&meta_size_bytes)?;
+        let meta_bytes = read_request_payload(rs, meta_size as MessagePayloadSize).await?;
+        let meta = PutRequestMeta::from_meta_bytes(&meta_bytes)?;
+        let data_size = payload_size
+            .checked_sub((SIZE_OF_PUT_META_SIZE + meta_size) as MessagePayloadSize)
+            .ok_or(anyhow!("payload size error"))?;
+
+        // store data & build response payload
+        let res_payload = self.store(meta, data_size, rs).await?;
+
+        // send response back
+        let mut response = build_message(MessageType::PutResponse, res_payload);
+        ss.write_all_chunks(response.as_mut_slice()).await?;
+        Ok(())
     }
 }
@@ -0,0 +1,144 @@
+use crate::command::{CommandClient, CommandServer};
+use crate::message::rm::{RmRequestPayload, RmResponsePayload};
+use crate::message::*;
+use crate::quic::client::Client;
+use crate::utils::dir::entry_in_root;
+use anyhow::{anyhow, Result};
+use quinn::VarInt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Removes a remote file or an empty remote dir
+pub async fn rm_remote(
+    client: &Client,
+    conn: &quinn::Connection,
+    remote_path: &Path,
+) -> Result<()> {
+    send_rm_request(client, conn, RmRequestPayload::new(remote_path)).await
+}
+
+/// A dir is removed with what a listing leaves out, like links and part files
+pub async fn rm_remote_dir(
+    client: &Client,
+    conn: &quinn::Connection,
+    remote_path: &Path,
+) -> Result<()> {
+    send_rm_request(client, conn, RmRequestPayload::recursive(remote_path)).await
+}
+
+async fn send_rm_request(
+    client: &Client,
+    conn: &quinn::Connection,
+    req_payload: RmRequestPayload,
+) -> Result<()> {
+    let remote_path = req_payload.remote_path.clone();
+    let response = client
+        .request(conn, MessageType::RmRequest, req_payload)
+        .await?;
+    client
+        .unwrap_message(&response, MessageType::RmResponse)?
+        .ok_or(anyhow!("remove remote path failed, path={remote_path:?}"))?;
+    Ok(())
+}
+
+pub struct RmCommandClient<'a> {
+    client: &'a Client,
+    remote_path: PathBuf,
+    recursive: bool,
+    force: bool,
+}
+
+impl<'a> RmCommandClient<'a> {
+    pub fn new(client: &'a Client, remote_path: &Path, recursive: bool, force: bool) -> Self {
+        Self {
+            client,
+            remote_path: remote_path.to_path_buf(),
+            recursive,
+            force,
+        }
+    }
+
+    async fn do_request(&self) -> Result<()> {
+        if !self.force && !self.confirm()? {
+            println!("canceled");
+            return Ok(());
+        }
+
+        // do request
+        let req_payload = if self.recursive {
+            RmRequestPayload::recursive(&self.remote_path)
+        } else {
+            RmRequestPayload::new(&self.remote_path)
+        };
+        let conn = self.client.connecting()?.await?;
+        send_rm_request(self.client, &conn, req_payload).await?;
+        conn.close(VarInt::from(200u32), "OK".as_bytes());
+
+        println!("rm: {:?}", self.remote_path);
+        println!("done");
+        Ok(())
+    }
+
+    /// Anything but a yes keeps the remote path, so does a closed stdin
+    fn confirm(&self) -> Result<bool> {
+        let what = if self.recursive {
+            " and everything in it"
+        } else {
+            ""
+        };
+        print!("remove remote path {:?}{what}? [y/N] ", self.remote_path);
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+    }
+}
+
+impl<'a> CommandClient for RmCommandClient<'a> {
+    async fn request(&self) -> Result<()> {
+        self.do_request().await.inspect_err(|e| {
+            println!("[ERR][Client] Process request error, error={e}");
+        })
+    }
+}
+
+pub struct RmCommandServer(PathBuf);
+
+impl RmCommandServer {
+    pub fn new(abs_root_dir: PathBuf) -> Self {
+        Self(abs_root_dir)
+    }
+}
+
+impl CommandServer for RmCommandServer {
+    async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage> {
+        // deserialize request payload
+        let payload = RmRequestPayload::from_payload(payload)?;
+
+        // check path valid, the root itself is never removed
+        let abs_path = entry_in_root(&self.0, &payload.remote_path)?;
+        if abs_path == self.0 {
+            return Err(anyhow!(
+                "refuse to remove root dir, path={:?}",
+                payload.remote_path
+            ));
+        }
+
+        // remove it, a link is removed rather than its target
+        if fs::symlink_metadata(&abs_path)?.is_dir() {
+            if payload.recursive {
+                fs::remove_dir_all(&abs_path)?;
+            } else {
+                fs::remove_dir(&abs_path)?;
+            }
+        } else {
+            fs::remove_file(&abs_path)?;
+        }
+
+        // build response message
+        let res_payload = RmResponsePayload::new(payload.remote_path);
+        Ok(build_message(MessageType::RmResponse, res_payload))
+    }
+}
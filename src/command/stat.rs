@@ -0,0 +1,123 @@
+use crate::command::{CommandClient, CommandServer};
+use crate::message::stat::{StatRequestPayload, StatResponsePayload};
+use crate::message::*;
+use crate::quic::client::Client;
+use crate::utils::dir::{entry_in_root, mtime_secs, permissions_string, DirItemType};
+use anyhow::{anyhow, Result};
+use chrono::{Local, TimeZone};
+use indicatif::HumanBytes;
+use quinn::VarInt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+pub struct StatCommandClient<'a> {
+    client: &'a Client,
+    remote_path: PathBuf,
+    json: bool,
+}
+
+impl<'a> StatCommandClient<'a> {
+    pub fn new(client: &'a Client, remote_path: &Path, json: bool) -> Self {
+        Self {
+            client,
+            remote_path: remote_path.to_path_buf(),
+            json,
+        }
+    }
+
+    async fn do_request(&self) -> Result<()> {
+        // do request
+        let conn = self.client.connecting()?.await?;
+        let response = self
+            .client
+            .request(
+                &conn,
+                MessageType::StatRequest,
+                StatRequestPayload::new(&self.remote_path),
+            )
+            .await?;
+        conn.close(VarInt::from(200u32), "OK".as_bytes());
+
+        // process response
+        let res_payload = self
+            .client
+            .unwrap_message(&response, MessageType::StatResponse)?
+            .ok_or(anyhow!(
+                "stat remote path failed, path={:?}",
+                self.remote_path
+            ))?;
+        let res_payload = StatResponsePayload::from_payload(res_payload)?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&res_payload)?);
+        } else {
+            self.print(&res_payload);
+        }
+
+        Ok(())
+    }
+
+    fn print(&self, stat: &StatResponsePayload) {
+        let mtime = Local
+            .timestamp_opt(stat.mtime as i64, 0)
+            .single()
+            .map_or(stat.mtime.to_string(), |mtime| {
+                mtime.format("%Y-%m-%d %H:%M:%S %:z").to_string()
+            });
+        println!("path : {:?}", stat.remote_path);
+        println!("type : {}", stat.kind.name());
+        println!("size : {} ({})", stat.size, HumanBytes(stat.size));
+        println!("mtime: {mtime}");
+        println!(
+            "mode : {:04o} ({})",
+            stat.mode,
+            permissions_string(stat.mode)
+        );
+        if let Some(target) = &stat.link_target {
+            println!("link : -> {target:?}");
+        }
+    }
+}
+
+impl<'a> CommandClient for StatCommandClient<'a> {
+    async fn request(&self) -> Result<()> {
+        self.do_request().await.inspect_err(|e| {
+            println!("[ERR][Client] Process request error, error={e}");
+        })
+    }
+}
+
+pub struct StatCommandServer(PathBuf);
+
+impl StatCommandServer {
+    pub fn new(abs_root_dir: PathBuf) -> Self {
+        Self(abs_root_dir)
+    }
+}
+
+impl CommandServer for StatCommandServer {
+    async fn handle(&self, payload: MessagePayloadRef<'_>) -> Result<SendMessage> {
+        // deserialize request payload
+        let payload = StatRequestPayload::from_payload(payload)?;
+
+        // check path valid, a link is described rather than its target
+        let abs_path = entry_in_root(&self.0, &payload.remote_path)?;
+        let meta = fs::symlink_metadata(&abs_path)?;
+        let kind = DirItemType::from(meta.file_type());
+        let link_target = match kind {
+            DirItemType::Symlink => Some(fs::read_link(&abs_path)?),
+            _ => None,
+        };
+
+        // build response message
+        let res_payload = StatResponsePayload {
+            remote_path: payload.remote_path,
+            kind,
+            size: meta.len(),
+            mtime: mtime_secs(&meta),
+            mode: meta.permissions().mode() & 0o7777,
+            link_target,
+        };
+        Ok(build_message(MessageType::StatResponse, res_payload))
+    }
+}
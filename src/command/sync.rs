@@ -0,0 +1,495 @@
+use crate::cli::{ConflictPolicy, LinkPolicy, SyncArgs, TransferArgs};
+use crate::command::digest::{remote_file_digest, remote_prefix_matches};
+use crate::command::get::GetCommandClient;
+use crate::command::ls::ls_remote;
+use crate::command::put::PutCommandClient;
+use crate::command::rm::{rm_remote, rm_remote_dir};
+use crate::command::CommandClient;
+use crate::message::ls::LsRequestPayload;
+use crate::quic::client::Client;
+use crate::utils::dir::{mtime_secs, walk_files, DirItem};
+use crate::utils::file::{file_digest, is_part_file};
+use crate::utils::state::{SyncState, SyncedFile, SYNC_STATE_FILE_NAME};
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use quinn::VarInt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::fs;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+struct SyncSummary {
+    new: usize,
+    updated: usize,
+    unchanged: usize,
+    deleted: usize,
+}
+
+#[derive(Default)]
+struct TwoWaySyncSummary {
+    put: usize,
+    got: usize,
+    deleted_local: usize,
+    deleted_remote: usize,
+    conflicts: usize,
+}
+
+#[derive(Debug)]
+enum SyncAction {
+    Put,
+    Get,
+    RmLocal,
+    RmRemote,
+    KeepBoth,
+}
+
+pub struct SyncCommandClient<'a> {
+    client: &'a Client,
+    local_dir: PathBuf,
+    remote_dir: PathBuf,
+    sync_options: SyncArgs,
+    options: TransferArgs,
+}
+
+impl<'a> SyncCommandClient<'a> {
+    pub fn new(
+        client: &'a Client,
+        local_dir: &Path,
+        remote_dir: &Path,
+        sync_options: SyncArgs,
+        options: TransferArgs,
+    ) -> Self {
+        Self {
+            client,
+            local_dir: local_dir.to_path_buf(),
+            remote_dir: remote_dir.to_path_buf(),
+            sync_options,
+            options,
+        }
+    }
+
+    async fn do_request(&self) -> Result<()> {
+        println!(
+            "sync local dir: {:?}, to remote dir: {:?}, time:{}",
+            self.local_dir,
+            self.remote_dir,
+            Local::now().timestamp_millis()
+        );
+
+        if !self.local_dir.is_dir() {
+            return Err(anyhow!(
+                "local path is not a dir, path={:?}",
+                self.local_dir
+            ));
+        }
+
+        // list both sides, a missing remote dir is synced as an empty one
+        let conn = self.client.connecting()?.await?;
+        let local_files = self.local_files()?;
+        let (remote_files, remote_dirs) = self.remote_listing(&conn).await?;
+        if self.sync_options.two_way {
+            self.sync_two_way(&conn, local_files, remote_files).await?;
+        } else {
+            self.sync_one_way(&conn, local_files, remote_files, remote_dirs)
+                .await?;
+        }
+        conn.close(VarInt::from(200u32), "OK".as_bytes());
+
+        println!(
+            "sync local dir: {:?}, to remote dir: {:?} finish, time:{}",
+            self.local_dir,
+            self.remote_dir,
+            Local::now().timestamp_millis()
+        );
+
+        Ok(())
+    }
+
+    /// Links are followed or skipped, a sync does not copy them as links
+    fn links(&self) -> LinkPolicy {
+        match self.options.links {
+            LinkPolicy::Follow => LinkPolicy::Follow,
+            LinkPolicy::CopyAsLink | LinkPolicy::Skip => LinkPolicy::Skip,
+        }
+    }
+
+    fn local_files(&self) -> Result<Vec<PathBuf>> {
+        let mut local_files = walk_files(&self.local_dir, self.links())?;
+        local_files.retain(|path| {
+            !path.starts_with(SYNC_STATE_FILE_NAME)
+                && !is_part_file(path)
+                && !self.local_dir.join(path).is_dir()
+        });
+        Ok(local_files)
+    }
+
+    async fn remote_listing(
+        &self,
+        conn: &quinn::Connection,
+    ) -> Result<(BTreeMap<PathBuf, DirItem>, BTreeSet<PathBuf>)> {
+        let mut remote_files = BTreeMap::new();
+        let mut remote_dirs = BTreeSet::new();
+        let mut req_payload = LsRequestPayload::recursive(&self.remote_dir);
+        req_payload.follow_links = self.links() == LinkPolicy::Follow;
+        if let Some(listing) = ls_remote(self.client, conn, req_payload).await? {
+            if !listing.is_dir {
+                return Err(anyhow!(
+                    "remote path is not a dir, path={:?}",
+                    self.remote_dir
+                ));
+            }
+            for item in listing.items {
+                // unfinished transfers are not synced
+                if item.is_file() && is_part_file(Path::new(&item.name())) {
+                    continue;
+                }
+                if item.is_file() {
+                    remote_files.insert(PathBuf::from(item.name()), item);
+                } else if item.is_dir() {
+                    remote_dirs.insert(PathBuf::from(item.name()));
+                }
+            }
+        }
+        Ok((remote_files, remote_dirs))
+    }
+
+    async fn sync_one_way(
+        &self,
+        conn: &quinn::Connection,
+        local_files: Vec<PathBuf>,
+        remote_files: BTreeMap<PathBuf, DirItem>,
+        remote_dirs: BTreeSet<PathBuf>,
+    ) -> Result<()> {
+        // put new or changed files
+        let mut summary = SyncSummary::default();
+        for rel_file_path in &local_files {
+            let local_file_path = self.local_dir.join(rel_file_path);
+            let remote_file_path = self.remote_dir.join(rel_file_path);
+            match remote_files.get(rel_file_path) {
+                None => {
+                    println!("new: {rel_file_path:?}");
+                    summary.new += 1;
+                }
+                Some(remote_item) => {
+                    if !self
+                        .is_changed(conn, &local_file_path, &remote_file_path, remote_item)
+                        .await?
+                    {
+                        summary.unchanged += 1;
+                        continue;
+                    }
+                    println!("update: {rel_file_path:?}");
+                    summary.updated += 1;
+                }
+            }
+            self.put_file(conn, rel_file_path).await?;
+        }
+
+        // delete remote files gone locally, then the dirs left empty, deepest first
+        if self.sync_options.delete {
+            let local_files = local_files.into_iter().collect::<BTreeSet<_>>();
+            for rel_file_path in remote_files.keys() {
+                if !local_files.contains(rel_file_path) {
+                    println!("delete: {rel_file_path:?}");
+                    rm_remote(self.client, conn, &self.remote_dir.join(rel_file_path)).await?;
+                    summary.deleted += 1;
+                }
+            }
+            for rel_dir in remote_dirs.iter().rev() {
+                if !self.local_dir.join(rel_dir).is_dir() {
+                    println!("delete: {rel_dir:?}");
+                    rm_remote_dir(self.client, conn, &self.remote_dir.join(rel_dir)).await?;
+                    summary.deleted += 1;
+                }
+            }
+        }
+
+        println!(
+            "sync summary: new={}, updated={}, unchanged={}, deleted={}",
+            summary.new, summary.updated, summary.unchanged, summary.deleted
+        );
+        Ok(())
+    }
+
+    async fn is_changed(
+        &self,
+        conn: &quinn::Connection,
+        local_file_path: &Path,
+        remote_file_path: &Path,
+        remote_item: &DirItem,
+    ) -> Result<bool> {
+        let meta = fs::metadata(local_file_path)?;
+        if meta.len() != remote_item.size() {
+            return Ok(true);
+        }
+        if self.sync_options.checksum {
+            // same size, so the whole file is the prefix
+            let same = remote_prefix_matches(
+                self.client,
+                conn,
+                local_file_path,
+                remote_file_path,
+                meta.len(),
+            )
+            .await?;
+            return Ok(!same);
+        }
+        Ok(mtime_secs(&meta) != remote_item.mtime())
+    }
+
+    async fn sync_two_way(
+        &self,
+        conn: &quinn::Connection,
+        local_files: Vec<PathBuf>,
+        remote_files: BTreeMap<PathBuf, DirItem>,
+    ) -> Result<()> {
+        let peer = format!("{}/{}", self.client.addr(), self.remote_dir.display());
+        let mut state = SyncState::load(&self.local_dir)?;
+        let synced_files = state.files(&peer);
+
+        // plan every path known to either side or to the last sync
+        let mut paths = local_files.iter().cloned().collect::<BTreeSet<_>>();
+        paths.extend(remote_files.keys().cloned());
+        paths.extend(synced_files.keys().cloned());
+        let mut actions = Vec::new();
+        let mut conflicts = Vec::new();
+        for rel_file_path in paths {
+            let local_file_path = self.local_dir.join(&rel_file_path);
+            let local_meta = fs::metadata(&local_file_path).ok().filter(|m| m.is_file());
+            let remote_item = remote_files.get(&rel_file_path);
+            let synced_file = synced_files.get(&rel_file_path);
+            let local_changed =
+                self.local_changed(&local_file_path, local_meta.as_ref(), synced_file)?;
+            let remote_changed = self
+                .remote_changed(conn, &rel_file_path, remote_item, synced_file)
+                .await?;
+
+            let action = match (local_changed, remote_changed) {
+                (false, false) => continue,
+                (true, false) if local_meta.is_some() => SyncAction::Put,
+                (true, false) if remote_item.is_some() => SyncAction::RmRemote,
+                (false, true) if remote_item.is_some() => SyncAction::Get,
+                (false, true) if local_meta.is_some() => SyncAction::RmLocal,
+                (true, true) => match (&local_meta, remote_item) {
+                    // a change always wins over a delete
+                    (Some(_), None) => SyncAction::Put,
+                    (None, Some(_)) => SyncAction::Get,
+                    (Some(meta), Some(remote_item)) => {
+                        if meta.len() == remote_item.size()
+                            && file_digest(&local_file_path)?
+                                == remote_file_digest(
+                                    self.client,
+                                    conn,
+                                    &self.remote_dir.join(&rel_file_path),
+                                )
+                                .await?
+                        {
+                            continue;
+                        }
+                        conflicts.push(rel_file_path.clone());
+                        match self.sync_options.conflict {
+                            ConflictPolicy::KeepBoth | ConflictPolicy::Abort => {
+                                SyncAction::KeepBoth
+                            }
+                            ConflictPolicy::NewerWins => {
+                                if mtime_secs(meta) >= remote_item.mtime() {
+                                    SyncAction::Put
+                                } else {
+                                    SyncAction::Get
+                                }
+                            }
+                        }
+                    }
+                    (None, None) => continue,
+                },
+                _ => continue,
+            };
+            actions.push((rel_file_path, action));
+        }
+        if self.sync_options.conflict == ConflictPolicy::Abort && !conflicts.is_empty() {
+            return Err(anyhow!(
+                "files changed on both sides, nothing synced, files={conflicts:?}"
+            ));
+        }
+
+        // carry out the plan
+        let mut summary = TwoWaySyncSummary::default();
+        let mut touched = BTreeSet::new();
+        for (rel_file_path, action) in actions {
+            println!("{action:?}: {rel_file_path:?}");
+            match action {
+                SyncAction::Put => {
+                    self.put_file(conn, &rel_file_path).await?;
+                    summary.put += 1;
+                }
+                SyncAction::Get => {
+                    self.get_file(conn, &rel_file_path).await?;
+                    summary.got += 1;
+                }
+                SyncAction::RmLocal => {
+                    fs::remove_file(self.local_dir.join(&rel_file_path))?;
+                    summary.deleted_local += 1;
+                }
+                SyncAction::RmRemote => {
+                    rm_remote(self.client, conn, &self.remote_dir.join(&rel_file_path)).await?;
+                    summary.deleted_remote += 1;
+                }
+                SyncAction::KeepBoth => {
+                    // the local version moves aside, both sides end up with both versions
+                    let conflict_file_path = conflict_file_path(&rel_file_path)?;
+                    fs::rename(
+                        self.local_dir.join(&rel_file_path),
+                        self.local_dir.join(&conflict_file_path),
+                    )?;
+                    self.get_file(conn, &rel_file_path).await?;
+                    self.put_file(conn, &conflict_file_path).await?;
+                    println!(
+                        "conflict: {rel_file_path:?}, local version kept as {conflict_file_path:?}"
+                    );
+                    summary.conflicts += 1;
+                    touched.insert(conflict_file_path);
+                }
+            }
+            touched.insert(rel_file_path);
+        }
+
+        // remember what both sides agree on now
+        let local_files = self.local_files()?;
+        let (remote_files, _) = self.remote_listing(conn).await?;
+        let mut agreed_files = BTreeMap::new();
+        for rel_file_path in local_files {
+            let Some(remote_item) = remote_files.get(&rel_file_path) else {
+                continue;
+            };
+            let local_file_path = self.local_dir.join(&rel_file_path);
+            let meta = fs::metadata(&local_file_path)?;
+            if meta.len() != remote_item.size() {
+                continue;
+            }
+            let digest = match synced_files.get(&rel_file_path) {
+                Some(synced_file)
+                    if !touched.contains(&rel_file_path)
+                        && synced_file.local_size == meta.len()
+                        && synced_file.local_mtime == mtime_secs(&meta) =>
+                {
+                    synced_file.digest.clone()
+                }
+                _ => file_digest(&local_file_path)?,
+            };
+            let synced_file = SyncedFile {
+                digest,
+                local_size: meta.len(),
+                local_mtime: mtime_secs(&meta),
+                remote_size: remote_item.size(),
+                remote_mtime: remote_item.mtime(),
+            };
+            agreed_files.insert(rel_file_path, synced_file);
+        }
+        state.set_files(&peer, agreed_files);
+        state.save(&self.local_dir)?;
+
+        println!(
+            "sync summary: put={}, got={}, deleted local={}, deleted remote={}, conflicts={}",
+            summary.put,
+            summary.got,
+            summary.deleted_local,
+            summary.deleted_remote,
+            summary.conflicts
+        );
+        Ok(())
+    }
+
+    fn local_changed(
+        &self,
+        local_file_path: &Path,
+        local_meta: Option<&Metadata>,
+        synced_file: Option<&SyncedFile>,
+    ) -> Result<bool> {
+        match (local_meta, synced_file) {
+            (None, None) => Ok(false),
+            (Some(meta), Some(synced_file)) => {
+                if meta.len() == synced_file.local_size
+                    && mtime_secs(meta) == synced_file.local_mtime
+                {
+                    return Ok(false);
+                }
+                Ok(file_digest(local_file_path)? != synced_file.digest)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    async fn remote_changed(
+        &self,
+        conn: &quinn::Connection,
+        rel_file_path: &Path,
+        remote_item: Option<&DirItem>,
+        synced_file: Option<&SyncedFile>,
+    ) -> Result<bool> {
+        match (remote_item, synced_file) {
+            (None, None) => Ok(false),
+            (Some(remote_item), Some(synced_file)) => {
+                if remote_item.size() == synced_file.remote_size
+                    && remote_item.mtime() == synced_file.remote_mtime
+                {
+                    return Ok(false);
+                }
+                let remote_file_path = self.remote_dir.join(rel_file_path);
+                let remote_digest =
+                    remote_file_digest(self.client, conn, &remote_file_path).await?;
+                Ok(remote_digest != synced_file.digest)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    async fn put_file(&self, conn: &quinn::Connection, rel_file_path: &Path) -> Result<()> {
+        let put = PutCommandClient::new(
+            self.client,
+            &self.local_dir,
+            &self.remote_dir,
+            self.options.clone(),
+        );
+        let remote_file_path = self.remote_dir.join(rel_file_path);
+        let remote_file_dir = remote_file_path
+            .parent()
+            .ok_or(anyhow!("got remote dir error"))?;
+        put.put_file(conn, &self.local_dir.join(rel_file_path), remote_file_dir)
+            .await
+    }
+
+    async fn get_file(&self, conn: &quinn::Connection, rel_file_path: &Path) -> Result<()> {
+        let get = GetCommandClient::new(
+            self.client,
+            &self.remote_dir,
+            &self.local_dir,
+            self.options.clone(),
+        );
+        let local_file_path = self.local_dir.join(rel_file_path);
+        let local_file_dir = local_file_path
+            .parent()
+            .ok_or(anyhow!("got local dir error"))?;
+        fs::create_dir_all(local_file_dir)?;
+        get.get_file(conn, &self.remote_dir.join(rel_file_path), local_file_dir)
+            .await
+    }
+}
+
+fn conflict_file_path(rel_file_path: &Path) -> Result<PathBuf> {
+    let file_name = rel_file_path
+        .file_name()
+        .ok_or(anyhow!("got file name error"))?;
+    let mut conflict_name = OsString::from(file_name);
+    conflict_name.push(format!(".conflict-{}", Local::now().format("%Y%m%d%H%M%S")));
+    Ok(rel_file_path.with_file_name(conflict_name))
+}
+
+impl<'a> CommandClient for SyncCommandClient<'a> {
+    async fn request(&self) -> Result<()> {
+        self.do_request().await.inspect_err(|e| {
+            println!("[ERR][Client] Process request error, error={e}");
+        })
+    }
+}
@@ -1,7 +1,12 @@
 use crate::cli::{ClientCommand, Command};
 use crate::command::get::GetCommandClient;
 use crate::command::ls::LsCommandClient;
+use crate::command::mkdir::MkdirCommandClient;
+use crate::command::mv::MvCommandClient;
 use crate::command::put::PutCommandClient;
+use crate::command::rm::RmCommandClient;
+use crate::command::stat::StatCommandClient;
+use crate::command::sync::SyncCommandClient;
 use crate::command::CommandClient;
 use crate::quic::client::Client;
 use crate::quic::server::Server;
@@ -17,24 +22,102 @@ mod utils;
 #[tokio::main]
 async fn main() -> Result<()> {
     match Command::parse() {
-        Command::Server { port, root_dir } => Server::new(port, &root_dir)?.start().await?,
+        Command::Server {
+            port,
+            root_dir,
+            max_chunk_size,
+            limit_rate,
+            limit_rate_per_conn,
+        } => {
+            Server::new(port, &root_dir, max_chunk_size)?
+                .limit_rate(limit_rate, limit_rate_per_conn)
+                .start()
+                .await?
+        }
         Command::Client { srv_addr, cmd } => {
             let client = Client::new(&srv_addr)?;
-            match cmd {
-                ClientCommand::Ls { remote_path } => {
-                    let cmd = LsCommandClient::new(&client, &remote_path);
-                    cmd.request().await;
+            let result = match cmd {
+                ClientCommand::Ls {
+                    remote_path,
+                    long,
+                    human_readable,
+                    sort,
+                    ..
+                } => {
+                    let cmd = LsCommandClient::new(&client, &remote_path).format(
+                        long,
+                        human_readable,
+                        sort,
+                    );
+                    cmd.request().await
+                }
+                ClientCommand::Stat { remote_path, json } => {
+                    let cmd = StatCommandClient::new(&client, &remote_path, json);
+                    cmd.request().await
+                }
+                ClientCommand::Mkdir {
+                    remote_path,
+                    parents,
+                } => {
+                    let cmd = MkdirCommandClient::new(&client, &remote_path, parents);
+                    cmd.request().await
+                }
+                ClientCommand::Rm {
+                    remote_path,
+                    recursive,
+                    force,
+                } => {
+                    let cmd = RmCommandClient::new(&client, &remote_path, recursive, force);
+                    cmd.request().await
                 }
-                ClientCommand::Put { file, remote_dir } => {
-                    let cmd = PutCommandClient::new(&client, &file, &remote_dir);
-                    cmd.request().await;
+                ClientCommand::Mv {
+                    src_path,
+                    dst_path,
+                    options,
+                } => {
+                    let cmd = MvCommandClient::new(&client, &src_path, &dst_path, options);
+                    cmd.request().await
                 }
-                ClientCommand::Get { file, local_dir } => {
-                    let cmd = GetCommandClient::new(&client, &file, &local_dir);
-                    cmd.request().await;
+                ClientCommand::Put {
+                    file,
+                    remote_dir,
+                    remote_name,
+                    options,
+                } => {
+                    let cmd = PutCommandClient::new(&client, &file, &remote_dir, options)
+                        .remote_name(remote_name);
+                    cmd.request().await
+                }
+                ClientCommand::Get {
+                    file,
+                    local_dir,
+                    options,
+                } => {
+                    let cmd = GetCommandClient::new(&client, &file, &local_dir, options);
+                    cmd.request().await
+                }
+                ClientCommand::Sync {
+                    local_dir,
+                    remote_dir,
+                    sync_options,
+                    options,
+                } => {
+                    let cmd = SyncCommandClient::new(
+                        &client,
+                        &local_dir,
+                        &remote_dir,
+                        sync_options,
+                        options,
+                    );
+                    cmd.request().await
                 }
             };
             client.wait().await;
+
+            // the error is printed by the command
+            if result.is_err() {
+                std::process::exit(1);
+            }
         }
     }
     Ok(())
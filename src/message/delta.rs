@@ -0,0 +1,136 @@
+use crate::message::*;
+use crate::utils::delta::{DeltaOp, FileSignature};
+use crate::utils::json::ToJsonString;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::mem::size_of;
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignatureRequestPayload {
+    pub remote_file_path: PathBuf,
+}
+
+impl SignatureRequestPayload {
+    pub fn new(remote_file_path: impl Into<PathBuf>) -> Self {
+        Self {
+            remote_file_path: remote_file_path.into(),
+        }
+    }
+}
+
+impl JsonPayload for SignatureRequestPayload {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignatureResponsePayload {
+    pub signature: FileSignature,
+}
+
+impl SignatureResponsePayload {
+    pub fn new(signature: FileSignature) -> Self {
+        Self { signature }
+    }
+}
+
+impl JsonPayload for SignatureResponsePayload {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PatchRequestMeta {
+    pub file_name: PathBuf,
+    pub remote_dir: PathBuf,
+    pub block_size: u64,
+    pub ops: Vec<DeltaOp>,
+    /// BLAKE3 digest of the source file, the rebuilt file must match it before it replaces the remote one
+    pub digest: String,
+}
+
+impl PatchRequestMeta {
+    pub fn new(
+        file_name: impl Into<PathBuf>,
+        remote_dir: impl Into<PathBuf>,
+        block_size: u64,
+        ops: Vec<DeltaOp>,
+        digest: impl Into<String>,
+    ) -> Self {
+        Self {
+            file_name: file_name.into(),
+            remote_dir: remote_dir.into(),
+            block_size,
+            ops,
+            digest: digest.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PatchResponsePayload {
+    pub file_len: u64,
+}
+
+impl PatchResponsePayload {
+    pub fn new(file_len: u64) -> Self {
+        Self { file_len }
+    }
+}
+
+impl JsonPayload for PatchResponsePayload {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeltaRequestPayload {
+    pub remote_file_path: PathBuf,
+    pub signature: FileSignature,
+}
+
+impl DeltaRequestPayload {
+    pub fn new(remote_file_path: impl Into<PathBuf>, signature: FileSignature) -> Self {
+        Self {
+            remote_file_path: remote_file_path.into(),
+            signature,
+        }
+    }
+}
+
+impl JsonPayload for DeltaRequestPayload {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeltaResponseMeta {
+    pub block_size: u64,
+    pub ops: Vec<DeltaOp>,
+}
+
+impl DeltaResponseMeta {
+    pub fn new(block_size: u64, ops: Vec<DeltaOp>) -> Self {
+        Self { block_size, ops }
+    }
+}
+
+pub const SIZE_OF_DELTA_META_SIZE: usize = size_of::<usize>();
+
+pub fn delta_meta_size(meta_size_bytes: &[u8]) -> Result<usize> {
+    if meta_size_bytes.len() != SIZE_OF_DELTA_META_SIZE {
+        return Err(anyhow!("payload size error"));
+    }
+    Ok(usize::from_le_bytes(meta_size_bytes.try_into()?))
+}
+
+/// The literal data is not part of the payload, it is streamed right after the meta
+pub struct DeltaPayload<T> {
+    pub meta: T,
+}
+
+impl<T> DeltaPayload<T> {
+    pub fn new(meta: T) -> Self {
+        Self { meta }
+    }
+}
+
+impl<T: Serialize> ToMessagePayload for DeltaPayload<T> {
+    fn to_payload(self) -> MessagePayload {
+        let meta_json = self.meta.to_json();
+        let mut buffer = Vec::from(meta_json.len().to_le_bytes());
+        buffer.extend(meta_json.into_bytes());
+        vec![Bytes::from(buffer)]
+    }
+}
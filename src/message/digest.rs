@@ -0,0 +1,43 @@
+use crate::message::JsonPayload;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DigestRequestPayload {
+    pub remote_file_path: PathBuf,
+    #[serde(default)]
+    pub prefix_len: Option<u64>,
+}
+
+impl DigestRequestPayload {
+    pub fn new(remote_file_path: impl Into<PathBuf>) -> Self {
+        Self {
+            remote_file_path: remote_file_path.into(),
+            prefix_len: None,
+        }
+    }
+
+    pub fn prefix(remote_file_path: impl Into<PathBuf>, prefix_len: u64) -> Self {
+        Self {
+            remote_file_path: remote_file_path.into(),
+            prefix_len: Some(prefix_len),
+        }
+    }
+}
+
+impl JsonPayload for DigestRequestPayload {}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct DigestResponsePayload {
+    pub digest: String,
+}
+
+impl DigestResponsePayload {
+    pub fn new(digest: impl Into<String>) -> Self {
+        Self {
+            digest: digest.into(),
+        }
+    }
+}
+
+impl JsonPayload for DigestResponsePayload {}
@@ -1,5 +1,7 @@
 use crate::message::*;
-use crate::utils::file::FileChunkSize;
+use crate::utils::codec::ChunkCodec;
+use crate::utils::file::{default_chunk_unit_size, FileAttrs, FileChunkSize};
+use crate::utils::json::ToJsonString;
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
@@ -7,104 +9,148 @@ use std::fmt::Debug;
 use std::mem::size_of;
 use std::path::PathBuf;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct GetRequestPayload {
     pub remote_file_path: PathBuf,
     pub local_file_chunk_size: FileChunkSize,
+    #[serde(default)]
+    pub chunk_index: Option<u64>,
+    #[serde(default = "default_chunk_unit_size")]
+    pub chunk_unit_size: usize,
+    #[serde(default)]
+    pub codec: ChunkCodec,
+    /// Chunks with holes may be sent as data segments only
+    #[serde(default)]
+    pub sparse: bool,
 }
 
 impl GetRequestPayload {
-    pub fn new(remote_file_path: impl Into<PathBuf>, local_file_chunk_size: FileChunkSize) -> Self {
+    pub fn new(
+        remote_file_path: impl Into<PathBuf>,
+        local_file_chunk_size: FileChunkSize,
+        codec: ChunkCodec,
+    ) -> Self {
+        let chunk_unit_size = local_file_chunk_size.chunk_unit_size();
         Self {
             remote_file_path: remote_file_path.into(),
             local_file_chunk_size,
+            chunk_index: None,
+            chunk_unit_size,
+            codec,
+            sparse: true,
+        }
+    }
+
+    pub fn chunk(
+        remote_file_path: impl Into<PathBuf>,
+        chunk_index: u64,
+        chunk_unit_size: usize,
+        codec: ChunkCodec,
+    ) -> Self {
+        Self {
+            remote_file_path: remote_file_path.into(),
+            local_file_chunk_size: Default::default(),
+            chunk_index: Some(chunk_index),
+            chunk_unit_size,
+            codec,
+            sparse: true,
         }
     }
 }
 
 impl JsonPayload for GetRequestPayload {}
 
-#[derive(Default, Debug)]
+/// The meta is sent as json after its length, so fields can be added without breaking older peers
+#[derive(Serialize, Deserialize, Default, Debug)]
 pub struct GetResponseMeta {
     pub remote_file_chunk_size: FileChunkSize,
     pub curr_trans_trunk_index: u64,
+    #[serde(default)]
+    pub chunk_checksum: u32,
+    #[serde(default)]
+    pub codec: ChunkCodec,
+    /// Missing when an older server sent the meta, the local attrs are kept then
+    #[serde(default)]
+    pub attrs: Option<FileAttrs>,
 }
 
 impl GetResponseMeta {
-    pub fn new(remote_file_chunk_size: FileChunkSize, curr_trans_trunk_index: u64) -> Self {
+    pub fn new(
+        remote_file_chunk_size: FileChunkSize,
+        curr_trans_trunk_index: u64,
+        chunk_checksum: u32,
+    ) -> Self {
         Self {
             remote_file_chunk_size,
             curr_trans_trunk_index,
+            chunk_checksum,
+            codec: ChunkCodec::Raw,
+            attrs: None,
         }
     }
-}
 
-impl From<GetResponseMeta> for Bytes {
-    fn from(value: GetResponseMeta) -> Self {
-        let mut buffer = Vec::new();
-        buffer.extend(value.remote_file_chunk_size.total_chunks().to_le_bytes());
-        buffer.extend(value.remote_file_chunk_size.rest_size().to_le_bytes());
-        buffer.extend(value.curr_trans_trunk_index.to_le_bytes());
-        Bytes::from(buffer)
+    pub fn from_meta_bytes(meta_bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice::<GetResponseMeta>(meta_bytes)?)
     }
-}
 
-impl From<&[u8]> for GetResponseMeta {
-    fn from(value: &[u8]) -> Self {
-        let (total_chunk_bytes, rest) = value.split_at(size_of::<u64>());
-        let total_chunk = u64::from_le_bytes(total_chunk_bytes.try_into().unwrap());
-
-        let (rest_size_bytes, curr_trans_trunk_index_bytes) = rest.split_at(size_of::<usize>());
-        let rest_size = usize::from_le_bytes(rest_size_bytes.try_into().unwrap());
-        let curr_trans_trunk_index =
-            u64::from_le_bytes(curr_trans_trunk_index_bytes.try_into().unwrap());
-
-        let remote_file_chunk_size = FileChunkSize::new(total_chunk, rest_size);
-        Self::new(remote_file_chunk_size, curr_trans_trunk_index)
+    pub fn meta_size(meta_size_bytes: &[u8]) -> Result<usize> {
+        if meta_size_bytes.len() != SIZE_OF_GET_META_SIZE {
+            return Err(anyhow!("payload size error"));
+        }
+        Ok(usize::from_le_bytes(meta_size_bytes.try_into()?))
     }
 }
 
-pub struct GetResponsePayload {
-    pub meta: GetResponseMeta,
-    pub data: Bytes,
-}
+pub const SIZE_OF_GET_META_SIZE: usize = size_of::<usize>();
 
-impl GetResponsePayload {
-    pub fn new(meta: GetResponseMeta, data: Bytes) -> Self {
-        Self { meta, data }
+impl From<GetResponseMeta> for Bytes {
+    fn from(value: GetResponseMeta) -> Self {
+        let meta_json = value.to_json();
+        let mut buffer = Vec::from(meta_json.len().to_le_bytes());
+        buffer.extend(meta_json.into_bytes());
+        Bytes::from(buffer)
     }
 }
 
-impl ToMessagePayload for GetResponsePayload {
-    fn to_payload(self) -> MessagePayload {
-        vec![self.meta.into(), self.data]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_response_meta_round_trip() {
+        let mut meta = GetResponseMeta::new(FileChunkSize::from_len(10, 4), 2, 0xdead_beef);
+        meta.codec = ChunkCodec::Zstd;
+        meta.attrs = Some(FileAttrs::new(1_700_000_000_000_000_000, 0o644));
+        let bytes = Bytes::from(meta);
+
+        let (meta_size_bytes, meta_bytes) = bytes.split_at(SIZE_OF_GET_META_SIZE);
+        assert_eq!(
+            GetResponseMeta::meta_size(meta_size_bytes).unwrap(),
+            meta_bytes.len()
+        );
+        let meta = GetResponseMeta::from_meta_bytes(meta_bytes).unwrap();
+        assert_eq!(meta.remote_file_chunk_size, FileChunkSize::from_len(10, 4));
+        assert_eq!(meta.curr_trans_trunk_index, 2);
+        assert_eq!(meta.chunk_checksum, 0xdead_beef);
+        assert_eq!(meta.codec, ChunkCodec::Zstd);
+        assert_eq!(
+            meta.attrs,
+            Some(FileAttrs::new(1_700_000_000_000_000_000, 0o644))
+        );
     }
-}
 
-pub struct GetResponsePayloadRef<'a> {
-    pub meta: GetResponseMeta,
-    pub data: &'a [u8],
-}
-
-impl<'a> GetResponsePayloadRef<'a> {
-    pub fn new(meta: GetResponseMeta, data: &'a [u8]) -> Self {
-        Self { meta, data }
+    #[test]
+    fn get_response_meta_defaults_missing_fields() {
+        let meta_bytes = br#"{"remote_file_chunk_size":[1,2,4],"curr_trans_trunk_index":0}"#;
+        let meta = GetResponseMeta::from_meta_bytes(meta_bytes).unwrap();
+        assert_eq!(meta.remote_file_chunk_size, FileChunkSize::new(1, 2, 4));
+        assert_eq!(meta.chunk_checksum, 0);
+        assert_eq!(meta.codec, ChunkCodec::Raw);
+        assert_eq!(meta.attrs, None);
     }
-}
-
-impl<'a> FromMessagePayloadRef<'a> for GetResponsePayloadRef<'a> {
-    fn from_payload(payload: MessagePayloadRef<'a>) -> Result<Self> {
-        // meta
-        let size_of_meta = size_of::<GetResponseMeta>();
-        if payload.len() < size_of_meta {
-            return Err(anyhow!("payload size error"));
-        }
-        let meta_bytes = &payload[..size_of_meta];
-        let meta = GetResponseMeta::from(meta_bytes);
-
-        // data
-        let data = &payload[size_of_meta..];
 
-        Ok(Self::new(meta, data))
+    #[test]
+    fn get_response_meta_size_needs_its_prefix() {
+        assert!(GetResponseMeta::meta_size(&[0; 3]).is_err());
     }
 }
@@ -6,12 +6,27 @@ use std::path::PathBuf;
 #[derive(Serialize, Deserialize)]
 pub struct LsRequestPayload {
     pub remote_path: PathBuf,
+    #[serde(default)]
+    pub recursive: bool,
+    /// Links resolved inside root are listed as their targets, and walked into
+    #[serde(default)]
+    pub follow_links: bool,
 }
 
 impl LsRequestPayload {
     pub fn new(remote_path: impl Into<PathBuf>) -> Self {
         Self {
             remote_path: remote_path.into(),
+            recursive: false,
+            follow_links: false,
+        }
+    }
+
+    pub fn recursive(remote_path: impl Into<PathBuf>) -> Self {
+        Self {
+            remote_path: remote_path.into(),
+            recursive: true,
+            follow_links: false,
         }
     }
 }
@@ -22,13 +37,16 @@ impl JsonPayload for LsRequestPayload {}
 pub struct LsResponsePayload {
     pub dir: PathBuf,
     pub items: Vec<DirItem>,
+    #[serde(default)]
+    pub is_dir: bool,
 }
 
 impl LsResponsePayload {
-    pub fn new(dir: impl Into<PathBuf>, items: Vec<DirItem>) -> Self {
+    pub fn new(dir: impl Into<PathBuf>, items: Vec<DirItem>, is_dir: bool) -> Self {
         Self {
             dir: dir.into(),
             items,
+            is_dir,
         }
     }
 }
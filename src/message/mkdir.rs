@@ -0,0 +1,35 @@
+use crate::message::JsonPayload;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MkdirRequestPayload {
+    pub remote_path: PathBuf,
+    pub parents: bool,
+}
+
+impl MkdirRequestPayload {
+    pub fn new(remote_path: impl Into<PathBuf>, parents: bool) -> Self {
+        Self {
+            remote_path: remote_path.into(),
+            parents,
+        }
+    }
+}
+
+impl JsonPayload for MkdirRequestPayload {}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct MkdirResponsePayload {
+    pub remote_path: PathBuf,
+}
+
+impl MkdirResponsePayload {
+    pub fn new(remote_path: impl Into<PathBuf>) -> Self {
+        Self {
+            remote_path: remote_path.into(),
+        }
+    }
+}
+
+impl JsonPayload for MkdirResponsePayload {}
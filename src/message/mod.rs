@@ -7,9 +7,15 @@ use num_enum_derive::{IntoPrimitive, TryFromPrimitive};
 use std::mem::size_of;
 use std::vec;
 
+pub mod delta;
+pub mod digest;
 pub mod get;
 pub mod ls;
+pub mod mkdir;
+pub mod mv;
 pub mod put;
+pub mod rm;
+pub mod stat;
 
 pub type SendMessage = Vec<Bytes>;
 pub type RecvMessage = Bytes;
@@ -19,18 +25,36 @@ pub type MessageMagic = u8;
 pub type MessagePayloadSize = u64;
 pub type MessagePayloadRef<'a> = &'a [u8];
 
+/// Types are numbered in sequence, a request takes an odd number and its response the next one,
+/// a new pair goes after the last one, and the numbers from 0xf0 on are kept for the special types
 #[repr(u16)]
 #[derive(Clone, Copy, Default, PartialEq, Eq, Debug, TryFromPrimitive, IntoPrimitive)]
 pub enum MessageType {
-    LsRequest = 0b00000001,
-    LsResponse = 0b00000010,
-    PutRequest = 0b00000100,
-    PutResponse = 0b00001000,
-    GetRequest = 0b00010000,
-    GetResponse = 0b00100000,
-    Error = 0b11110000,
+    LsRequest = 1,
+    LsResponse = 2,
+    PutRequest = 3,
+    PutResponse = 4,
+    GetRequest = 5,
+    GetResponse = 6,
+    DigestRequest = 7,
+    DigestResponse = 8,
+    SignatureRequest = 9,
+    SignatureResponse = 10,
+    PatchRequest = 11,
+    PatchResponse = 12,
+    DeltaRequest = 13,
+    DeltaResponse = 14,
+    RmRequest = 15,
+    RmResponse = 16,
+    MkdirRequest = 17,
+    MkdirResponse = 18,
+    MvRequest = 19,
+    MvResponse = 20,
+    StatRequest = 21,
+    StatResponse = 22,
+    Error = 0xf0,
     #[default]
-    Invalid = 0b11111111,
+    Invalid = 0xff,
 }
 
 impl MessageType {
@@ -59,8 +83,9 @@ const MESSAGE_MAGIC: MessageMagic = b'l' ^ b'a' ^ b'n' ^ b't';
 const SIZE_OF_MESSAGE_MAGIC: usize = size_of::<MessageMagic>();
 const SIZE_OF_MESSAGE_TYPE: usize = size_of::<MessageType>();
 const SIZE_OF_MESSAGE_PAYLOAD_SIZE: usize = size_of::<MessagePayloadSize>();
-const SIZE_OF_HEADER: usize =
+pub const SIZE_OF_HEADER: usize =
     SIZE_OF_MESSAGE_MAGIC + SIZE_OF_MESSAGE_TYPE + SIZE_OF_MESSAGE_PAYLOAD_SIZE;
+pub const MAX_REQUEST_PAYLOAD_SIZE: MessagePayloadSize = 1024 * 1024;
 
 /// ```
 /// ┌───────┬───────┬───┬───────┬───┬───┬───┬───┬───┬───┬───┬───────┬──~~~──┐
@@ -72,6 +97,27 @@ pub fn deconstruct_message(
 ) -> Result<(MessageType, Option<MessagePayloadRef<'_>>)> {
     let mut cursor = Cursor::new(msg);
 
+    // header valid
+    let (msg_type, payload_size) = deconstruct_header(msg)?;
+    cursor.seek(SIZE_OF_HEADER as u64)?;
+
+    // total size valid
+    if cursor.total_size() != SIZE_OF_HEADER + payload_size as usize {
+        return Err(anyhow!("message size invalid"));
+    }
+
+    let mut payload = None;
+    if payload_size > 0 {
+        let payload_bytes = cursor.rest()?;
+        payload = Some(payload_bytes as MessagePayloadRef);
+    }
+
+    Ok((msg_type, payload))
+}
+
+pub fn deconstruct_header(header: &[u8]) -> Result<(MessageType, MessagePayloadSize)> {
+    let mut cursor = Cursor::new(header);
+
     // header size valid
     if cursor.total_size() < SIZE_OF_HEADER {
         return Err(anyhow!("message header size invalid"));
@@ -89,25 +135,23 @@ pub fn deconstruct_message(
         return Err(anyhow!("message type invalid"));
     }
 
-    // total size valid
     let payload_size = cursor.read_num_fle::<MessagePayloadSize>()?;
-    if cursor.total_size() != SIZE_OF_HEADER + payload_size as usize {
-        return Err(anyhow!("message size invalid"));
-    }
-
-    let mut payload = None;
-    if payload_size > 0 {
-        let payload_bytes = cursor.rest()?;
-        payload = Some(payload_bytes as MessagePayloadRef);
-    }
-
-    Ok((msg_type, payload))
+    Ok((msg_type, payload_size))
 }
 
 pub fn build_message(msg_type: MessageType, payload: impl ToMessagePayload) -> SendMessage {
+    build_stream_message(msg_type, payload, 0)
+}
+
+/// Builds a message whose payload is followed by `data_size` bytes written to the stream later
+pub fn build_stream_message(
+    msg_type: MessageType,
+    payload: impl ToMessagePayload,
+    data_size: u64,
+) -> SendMessage {
     let magic = Bytes::copy_from_slice(&MESSAGE_MAGIC.to_le_bytes());
     let msg_type = Bytes::copy_from_slice(&msg_type.to_num().to_le_bytes());
-    let mut total_payload_size = 0;
+    let mut total_payload_size = data_size as usize;
     let mut payload = payload.to_payload();
     for chunked in &payload {
         total_payload_size += chunked.len();
@@ -0,0 +1,53 @@
+use crate::message::JsonPayload;
+use crate::utils::file::ExistingFilePolicy;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MvRequestPayload {
+    pub src_path: PathBuf,
+    /// An existing dir takes the source into it, under the source name
+    pub dst_path: PathBuf,
+    pub if_exists: ExistingFilePolicy,
+}
+
+impl MvRequestPayload {
+    pub fn new(
+        src_path: impl Into<PathBuf>,
+        dst_path: impl Into<PathBuf>,
+        if_exists: ExistingFilePolicy,
+    ) -> Self {
+        Self {
+            src_path: src_path.into(),
+            dst_path: dst_path.into(),
+            if_exists,
+        }
+    }
+}
+
+impl JsonPayload for MvRequestPayload {}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct MvResponsePayload {
+    /// Where the source ended up, relative to root
+    pub dst_path: PathBuf,
+    pub skipped: bool,
+}
+
+impl MvResponsePayload {
+    pub fn new(dst_path: impl Into<PathBuf>) -> Self {
+        Self {
+            dst_path: dst_path.into(),
+            skipped: false,
+        }
+    }
+
+    pub fn skip(dst_path: impl Into<PathBuf>) -> Self {
+        Self {
+            dst_path: dst_path.into(),
+            skipped: true,
+        }
+    }
+}
+
+impl JsonPayload for MvResponsePayload {}
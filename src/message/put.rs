@@ -1,5 +1,6 @@
 use crate::message::*;
-use crate::utils::file::FileChunkSize;
+use crate::utils::codec::ChunkCodec;
+use crate::utils::file::{default_chunk_unit_size, ExistingFilePolicy, FileAttrs, FileChunkSize};
 use crate::utils::json::ToJsonString;
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
@@ -13,79 +14,79 @@ pub struct PutRequestMeta {
     pub file_name: PathBuf,
     pub remote_dir: PathBuf,
     pub curr_trans_trunk_index: u64,
+    #[serde(default = "default_chunk_unit_size")]
+    pub chunk_unit_size: usize,
+    #[serde(default)]
+    pub chunk_checksum: u32,
+    #[serde(default)]
+    pub restart: bool,
+    #[serde(default)]
+    pub codec: ChunkCodec,
+    /// Only the first request of a file carries it, the rest resume what it opened
+    #[serde(default)]
+    pub if_exists: ExistingFilePolicy,
+    /// Only the last request of a file carries it, unless preserving is disabled
+    #[serde(default)]
+    pub attrs: Option<FileAttrs>,
+    /// Creates a link to the target instead of storing data
+    #[serde(default)]
+    pub link_target: Option<PathBuf>,
     pub is_done: bool,
 }
 
 impl PutRequestMeta {
-    pub fn new(file_name: impl Into<PathBuf>, remote_dir: impl Into<PathBuf>) -> Self {
+    pub fn new(
+        file_name: impl Into<PathBuf>,
+        remote_dir: impl Into<PathBuf>,
+        chunk_unit_size: usize,
+        codec: ChunkCodec,
+    ) -> Self {
         Self {
             file_name: file_name.into(),
             remote_dir: remote_dir.into(),
             curr_trans_trunk_index: 0,
+            chunk_unit_size,
+            chunk_checksum: 0,
+            restart: false,
+            codec,
+            if_exists: ExistingFilePolicy::Resume,
+            attrs: None,
+            link_target: None,
             is_done: false,
         }
     }
+
+    pub fn from_meta_bytes(meta_bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice::<PutRequestMeta>(meta_bytes)?)
+    }
+
+    pub fn meta_size(meta_size_bytes: &[u8]) -> Result<usize> {
+        if meta_size_bytes.len() != SIZE_OF_PUT_META_SIZE {
+            return Err(anyhow!("payload size error"));
+        }
+        Ok(usize::from_le_bytes(meta_size_bytes.try_into()?))
+    }
 }
 
+pub const SIZE_OF_PUT_META_SIZE: usize = size_of::<usize>();
+
+/// The chunk data is not part of the payload, it is streamed right after the meta
 pub struct PutRequestPayload {
     pub meta: PutRequestMeta,
-    pub data: Option<Bytes>,
 }
 
 impl PutRequestPayload {
-    pub fn new(meta: PutRequestMeta, data: Option<Bytes>) -> Self {
-        Self { meta, data }
+    pub fn new(meta: PutRequestMeta) -> Self {
+        Self { meta }
     }
 }
 
 impl ToMessagePayload for PutRequestPayload {
     fn to_payload(self) -> MessagePayload {
-        let mut chunked_payload = Vec::new();
         let meta_json = self.meta.to_json();
         let mut buffer = Vec::from(meta_json.len().to_le_bytes());
         buffer.extend(meta_json.into_bytes());
-        chunked_payload.push(Bytes::from(buffer));
-        if let Some(data) = self.data {
-            chunked_payload.push(data);
-        }
-        chunked_payload
-    }
-}
-
-pub struct PutRequestPayloadRef<'a> {
-    pub meta: PutRequestMeta,
-    pub data: &'a [u8],
-}
-
-impl<'a> PutRequestPayloadRef<'a> {
-    pub fn new(meta: PutRequestMeta, data: &'a [u8]) -> Self {
-        Self { meta, data }
-    }
-}
-
-impl<'a> FromMessagePayloadRef<'a> for PutRequestPayloadRef<'a> {
-    fn from_payload(payload: MessagePayloadRef<'a>) -> Result<Self> {
-        // meta size
-        let size_of_meta_size = size_of::<usize>();
-        if payload.len() < size_of_meta_size {
-            return Err(anyhow!("payload size error"));
-        }
-        let meta_size_bytes = &payload[..size_of_meta_size];
-        let meta_size = usize::from_le_bytes(meta_size_bytes.try_into()?);
-
-        // meta
-        if payload.len() < size_of_meta_size + meta_size {
-            return Err(anyhow!("payload size error"));
-        }
-        let meta_offset = size_of_meta_size;
-        let meta_bytes = &payload[meta_offset..meta_offset + meta_size];
-        let meta = serde_json::from_slice::<PutRequestMeta>(meta_bytes)?;
-
-        // data
-        let data_offset = meta_offset + meta_size;
-        let data = &payload[data_offset..];
-
-        Ok(Self::new(meta, data))
+        vec![Bytes::from(buffer)]
     }
 }
 
@@ -93,13 +94,32 @@ impl<'a> FromMessagePayloadRef<'a> for PutRequestPayloadRef<'a> {
 pub struct PutResponsePayload {
     pub remote_file_chunk_size: FileChunkSize,
     pub is_done: bool,
+    #[serde(default)]
+    pub chunk_mismatch: bool,
+    #[serde(default)]
+    pub codec: ChunkCodec,
+    #[serde(default)]
+    pub file_name: Option<PathBuf>,
+    #[serde(default)]
+    pub skipped: bool,
+    #[serde(default)]
+    pub is_part: bool,
+    /// Chunks with holes may be sent as data segments only
+    #[serde(default)]
+    pub sparse: bool,
 }
 
 impl PutResponsePayload {
-    pub fn new(remote_file_chunk_size: FileChunkSize) -> Self {
+    pub fn new(remote_file_chunk_size: FileChunkSize, codec: ChunkCodec) -> Self {
         Self {
             remote_file_chunk_size,
             is_done: false,
+            chunk_mismatch: false,
+            codec,
+            file_name: None,
+            skipped: false,
+            is_part: true,
+            sparse: true,
         }
     }
 
@@ -107,6 +127,38 @@ impl PutResponsePayload {
         Self {
             remote_file_chunk_size: Default::default(),
             is_done: true,
+            chunk_mismatch: false,
+            codec: ChunkCodec::Raw,
+            file_name: None,
+            skipped: false,
+            is_part: false,
+            sparse: false,
+        }
+    }
+
+    pub fn skip() -> Self {
+        Self {
+            remote_file_chunk_size: Default::default(),
+            is_done: true,
+            chunk_mismatch: false,
+            codec: ChunkCodec::Raw,
+            file_name: None,
+            skipped: true,
+            is_part: false,
+            sparse: false,
+        }
+    }
+
+    pub fn mismatch(remote_file_chunk_size: FileChunkSize) -> Self {
+        Self {
+            remote_file_chunk_size,
+            is_done: false,
+            chunk_mismatch: true,
+            codec: ChunkCodec::Raw,
+            file_name: None,
+            skipped: false,
+            is_part: true,
+            sparse: false,
         }
     }
 }
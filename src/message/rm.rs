@@ -0,0 +1,44 @@
+use crate::message::JsonPayload;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RmRequestPayload {
+    pub remote_path: PathBuf,
+    /// A dir is removed with everything in it, otherwise only an empty one is
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+impl RmRequestPayload {
+    pub fn new(remote_path: impl Into<PathBuf>) -> Self {
+        Self {
+            remote_path: remote_path.into(),
+            recursive: false,
+        }
+    }
+
+    pub fn recursive(remote_path: impl Into<PathBuf>) -> Self {
+        Self {
+            remote_path: remote_path.into(),
+            recursive: true,
+        }
+    }
+}
+
+impl JsonPayload for RmRequestPayload {}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct RmResponsePayload {
+    pub remote_path: PathBuf,
+}
+
+impl RmResponsePayload {
+    pub fn new(remote_path: impl Into<PathBuf>) -> Self {
+        Self {
+            remote_path: remote_path.into(),
+        }
+    }
+}
+
+impl JsonPayload for RmResponsePayload {}
@@ -0,0 +1,34 @@
+use crate::message::JsonPayload;
+use crate::utils::dir::DirItemType;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatRequestPayload {
+    pub remote_path: PathBuf,
+}
+
+impl StatRequestPayload {
+    pub fn new(remote_path: impl Into<PathBuf>) -> Self {
+        Self {
+            remote_path: remote_path.into(),
+        }
+    }
+}
+
+impl JsonPayload for StatRequestPayload {}
+
+/// The metadata of a link is its own, its target is only named
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatResponsePayload {
+    pub remote_path: PathBuf,
+    pub kind: DirItemType,
+    pub size: u64,
+    /// Seconds since the unix epoch
+    pub mtime: u64,
+    /// Permission bits, with setuid, setgid and sticky
+    pub mode: u32,
+    pub link_target: Option<PathBuf>,
+}
+
+impl JsonPayload for StatResponsePayload {}
@@ -4,17 +4,9 @@ use anyhow::Result;
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use rustls::pki_types::CertificateDer;
-use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-#[derive(Default, Serialize, Deserialize)]
-pub enum Stage<T> {
-    Processing(T),
-    #[default]
-    Finish,
-}
-
 pub struct Client {
     quic_client: QuicClient,
 }
@@ -29,25 +21,67 @@ impl Client {
         self.quic_client.connecting()
     }
 
+    pub fn addr(&self) -> SocketAddr {
+        self.quic_client.addr
+    }
+
     pub async fn request(
         &self,
         conn: &quinn::Connection,
         msg_type: MessageType,
         payload: impl ToMessagePayload,
     ) -> Result<RecvMessage> {
-        // build request message
-        let mut msg = build_message(msg_type, payload);
-
         // connect & send request
-        let (mut ss, mut rs) = conn.open_bi().await?;
-        ss.write_all_chunks(msg.as_mut_slice()).await?;
+        let (mut ss, mut rs) = self.request_stream(conn, msg_type, payload, 0).await?;
         ss.finish()?;
 
         // receive response
+        self.recv_message(&mut rs).await
+    }
+
+    pub async fn recv_message(&self, rs: &mut quinn::RecvStream) -> Result<RecvMessage> {
         let response = rs.read_to_end(usize::MAX).await?;
         Ok(response.into())
     }
 
+    pub async fn request_stream(
+        &self,
+        conn: &quinn::Connection,
+        msg_type: MessageType,
+        payload: impl ToMessagePayload,
+        data_size: u64,
+    ) -> Result<(quinn::SendStream, quinn::RecvStream)> {
+        // build request message
+        let mut msg = build_stream_message(msg_type, payload, data_size);
+
+        // connect & send request, the data is left to the caller
+        let (mut ss, rs) = conn.open_bi().await?;
+        ss.write_all_chunks(msg.as_mut_slice()).await?;
+        Ok((ss, rs))
+    }
+
+    pub async fn recv_stream_header(
+        &self,
+        rs: &mut quinn::RecvStream,
+        msg_type_expect: MessageType,
+    ) -> Result<Option<MessagePayloadSize>> {
+        let mut header = [0; SIZE_OF_HEADER];
+        rs.read_exact(&mut header).await?;
+        let (msg_type, payload_size) = deconstruct_header(&header)?;
+        match msg_type {
+            msg_type if msg_type == msg_type_expect => Ok(Some(payload_size)),
+            MessageType::Error => {
+                let payload = rs.read_to_end(usize::MAX).await?;
+                eprintln!("[ERR]{}", std::str::from_utf8(&payload)?);
+                Ok(None)
+            }
+            msg_type => {
+                eprintln!("[ERR]{msg_type:?} not fit");
+                Ok(None)
+            }
+        }
+    }
+
     pub fn unwrap_message<'a>(
         &self,
         msg: &'a RecvMessage,
@@ -58,12 +92,12 @@ impl Client {
             msg_type if msg_type == msg_type_expect => Ok(msg_payload),
             MessageType::Error => {
                 if let Some(payload) = msg_payload {
-                    println!("[ERR]{}", std::str::from_utf8(payload)?);
+                    eprintln!("[ERR]{}", std::str::from_utf8(payload)?);
                 }
                 Ok(None)
             }
             msg_type => {
-                println!("[ERR]{msg_type:?} not fit");
+                eprintln!("[ERR]{msg_type:?} not fit");
                 Ok(None)
             }
         }
@@ -1,9 +1,17 @@
+use crate::command::delta::{DeltaCommandServer, PatchCommandServer, SignatureCommandServer};
+use crate::command::digest::DigestCommandServer;
 use crate::command::get::GetCommandServer;
 use crate::command::ls::LsCommandServer;
+use crate::command::mkdir::MkdirCommandServer;
+use crate::command::mv::MvCommandServer;
 use crate::command::put::PutCommandServer;
-use crate::command::CommandServer;
+use crate::command::rm::RmCommandServer;
+use crate::command::stat::StatCommandServer;
+use crate::command::{CommandServer, StreamCommandServer};
 use crate::message::*;
 use crate::quic::cert::{LTS_CERT, LTS_KEY};
+use crate::utils::rate::RateLimiter;
+use crate::utils::stream::read_request_payload;
 use anyhow::{anyhow, Result};
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
@@ -23,12 +31,15 @@ use tokio::try_join;
 
 pub struct Server {
     root_dir: PathBuf,
+    max_chunk_size: usize,
+    limiter: Option<RateLimiter>,
+    conn_limit_rate: Option<u64>,
     quic_server: QuicServer,
     conn_receiver: Rc<Receiver<quinn::Connection>>,
 }
 
 impl Server {
-    pub fn new(port: u16, root_dir: &Path) -> Result<Self> {
+    pub fn new(port: u16, root_dir: &Path, max_chunk_size: usize) -> Result<Self> {
         // root path check
         if !root_dir.is_dir() {
             return Err(anyhow!("root path is not a dir"));
@@ -43,11 +54,25 @@ impl Server {
 
         Ok(Self {
             root_dir: root_dir.to_path_buf(),
+            max_chunk_size,
+            limiter: None,
+            conn_limit_rate: None,
             quic_server,
             conn_receiver,
         })
     }
 
+    /// Caps the chunk data rate over all connections, and on each connection
+    pub fn limit_rate(
+        mut self,
+        limit_rate: Option<usize>,
+        limit_rate_per_conn: Option<usize>,
+    ) -> Self {
+        self.limiter = limit_rate.map(|rate| RateLimiter::new(rate as u64));
+        self.conn_limit_rate = limit_rate_per_conn.map(|rate| rate as u64);
+        self
+    }
+
     pub fn get_server_abs_root_dir(&self) -> Result<PathBuf> {
         let root_dir = &self.root_dir;
         Ok(root_dir.absolutize()?.to_path_buf())
@@ -76,7 +101,12 @@ impl Server {
                         "[Server] Receive a connection, from {:?}",
                         conn.remote_address()
                     );
-                    tokio::spawn(handle_requests(abs_root_path.clone(), conn));
+                    tokio::spawn(handle_requests(
+                        abs_root_path.clone(),
+                        self.max_chunk_size,
+                        RateLimiter::child(self.limiter.clone(), self.conn_limit_rate),
+                        conn,
+                    ));
                 }
                 Err(e) => match e {
                     TryRecvError::Empty => {
@@ -169,11 +199,22 @@ async fn handle_incoming(incoming: quinn::Incoming, conn_sender: Arc<Sender<quin
     }
 }
 
-async fn handle_requests(abs_root_dir: PathBuf, conn: quinn::Connection) {
+async fn handle_requests(
+    abs_root_dir: PathBuf,
+    max_chunk_size: usize,
+    limiter: Option<RateLimiter>,
+    conn: quinn::Connection,
+) {
     loop {
         match conn.accept_bi().await {
             Ok((ss, rs)) => {
-                tokio::spawn(handle_request(abs_root_dir.clone(), ss, rs));
+                tokio::spawn(handle_request(
+                    abs_root_dir.clone(),
+                    max_chunk_size,
+                    limiter.clone(),
+                    ss,
+                    rs,
+                ));
             }
             e @ Err(
                 quinn::ConnectionError::ConnectionClosed(_)
@@ -201,38 +242,115 @@ async fn handle_requests(abs_root_dir: PathBuf, conn: quinn::Connection) {
 
 async fn handle_request(
     abs_root_dir: PathBuf,
+    max_chunk_size: usize,
+    limiter: Option<RateLimiter>,
     mut ss: quinn::SendStream,
     mut rs: quinn::RecvStream,
 ) {
-    // receive request data
-    if let Ok(request) = rs.read_to_end(usize::MAX).await {
-        // do business and build response
-        let res_msg = handle_business(abs_root_dir.clone(), request.into()).await;
-        let mut response = res_msg.unwrap_or_else(|e| build_error_message(e.to_string()));
+    // do business, the response is sent by the business itself
+    if let Err(e) =
+        handle_stream_business(abs_root_dir, max_chunk_size, limiter, &mut ss, &mut rs).await
+    {
+        let mut response = build_error_message(e.to_string());
 
-        // send response back
+        // send error back
         if let Err(e) = ss.write_all_chunks(response.as_mut_slice()).await {
             println!("[ERR][Server] Send back message error, error={e}");
         }
     }
 }
 
-async fn handle_business(abs_root_dir: PathBuf, msg: RecvMessage) -> Result<SendMessage> {
-    let (msg_type, msg_payload) = deconstruct_message(&msg)?;
-    let req_payload = msg_payload.ok_or(anyhow!("request body is null"))?;
+async fn handle_stream_business(
+    abs_root_dir: PathBuf,
+    max_chunk_size: usize,
+    limiter: Option<RateLimiter>,
+    ss: &mut quinn::SendStream,
+    rs: &mut quinn::RecvStream,
+) -> Result<()> {
+    // receive request header
+    let mut header = [0; SIZE_OF_HEADER];
+    rs.read_exact(&mut header).await?;
+    let (msg_type, payload_size) = deconstruct_header(&header)?;
+
+    match msg_type {
+        MessageType::PutRequest => {
+            PutCommandServer::new(abs_root_dir, max_chunk_size, limiter)
+                .handle_stream(payload_size, rs, ss)
+                .await
+        }
+        MessageType::GetRequest => {
+            GetCommandServer::new(abs_root_dir, max_chunk_size, limiter)
+                .handle_stream(payload_size, rs, ss)
+                .await
+        }
+        MessageType::PatchRequest => {
+            PatchCommandServer::new(abs_root_dir, limiter)
+                .handle_stream(payload_size, rs, ss)
+                .await
+        }
+        MessageType::DeltaRequest => {
+            DeltaCommandServer::new(abs_root_dir, limiter)
+                .handle_stream(payload_size, rs, ss)
+                .await
+        }
+        msg_type => {
+            // receive request payload
+            let payload = read_request_payload(rs, payload_size).await?;
+
+            // do business and build response
+            let res_msg = handle_business(abs_root_dir, msg_type, &payload).await;
+            let mut response = res_msg.unwrap_or_else(|e| build_error_message(e.to_string()));
+
+            // send response back
+            ss.write_all_chunks(response.as_mut_slice()).await?;
+            Ok(())
+        }
+    }
+}
+
+async fn handle_business(
+    abs_root_dir: PathBuf,
+    msg_type: MessageType,
+    payload: MessagePayloadRef<'_>,
+) -> Result<SendMessage> {
+    if payload.is_empty() {
+        return Err(anyhow!("request body is null"));
+    }
+    let req_payload = payload;
+
     match msg_type {
         MessageType::LsRequest => {
             LsCommandServer::new(abs_root_dir.clone())
                 .handle(req_payload)
                 .await
         }
-        MessageType::PutRequest => {
-            PutCommandServer::new(abs_root_dir.clone())
+        MessageType::DigestRequest => {
+            DigestCommandServer::new(abs_root_dir.clone())
                 .handle(req_payload)
                 .await
         }
-        MessageType::GetRequest => {
-            GetCommandServer::new(abs_root_dir.clone())
+        MessageType::SignatureRequest => {
+            SignatureCommandServer::new(abs_root_dir.clone())
+                .handle(req_payload)
+                .await
+        }
+        MessageType::RmRequest => {
+            RmCommandServer::new(abs_root_dir.clone())
+                .handle(req_payload)
+                .await
+        }
+        MessageType::MkdirRequest => {
+            MkdirCommandServer::new(abs_root_dir.clone())
+                .handle(req_payload)
+                .await
+        }
+        MessageType::MvRequest => {
+            MvCommandServer::new(abs_root_dir.clone())
+                .handle(req_payload)
+                .await
+        }
+        MessageType::StatRequest => {
+            StatCommandServer::new(abs_root_dir.clone())
                 .handle(req_payload)
                 .await
         }
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ChunkCodec {
+    #[default]
+    Raw,
+    Zstd,
+    /// Only the data segments of a chunk, its holes are left unwritten
+    Sparse,
+}
+
+impl ChunkCodec {
+    pub fn preferred(compress: bool) -> Self {
+        if compress {
+            ChunkCodec::Zstd
+        } else {
+            ChunkCodec::Raw
+        }
+    }
+}
@@ -0,0 +1,527 @@
+use crate::utils::rate::RateLimiter;
+use crate::utils::stream::FileRange;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncRead;
+
+pub const MIN_DELTA_BLOCK_SIZE: u64 = 2048;
+
+/// Keeps signatures and ops small enough to fit in one request payload
+pub const MAX_DELTA_BLOCKS: u64 = 16 * 1024;
+
+const WINDOW_READ_SIZE: usize = 1024 * 1024;
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+pub fn delta_block_size(file_len: u64) -> u64 {
+    let block_size = max(file_len.isqrt(), file_len.div_ceil(MAX_DELTA_BLOCKS));
+    max(block_size, MIN_DELTA_BLOCK_SIZE)
+}
+
+/// The file a delta is applied to before it replaces the destination
+pub fn delta_temp_path(file_path: &Path) -> Result<PathBuf> {
+    let file_name = file_path
+        .file_name()
+        .ok_or(anyhow!("got file name error"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".lant-delta");
+    Ok(file_path.with_file_name(temp_name))
+}
+
+/// rsync style weak checksum, rolls one byte at a time
+struct RollingChecksum {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl RollingChecksum {
+    fn new(data: &[u8]) -> Self {
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        let len = data.len() as u32;
+        for (i, byte) in data.iter().enumerate() {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(*byte as u32));
+        }
+        Self { a, b, len }
+    }
+
+    fn roll(&mut self, out_byte: u8, in_byte: u8) {
+        self.a = self
+            .a
+            .wrapping_sub(out_byte as u32)
+            .wrapping_add(in_byte as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out_byte as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong_checksum(data: &[u8]) -> String {
+    blake3::hash(data).to_hex()[..32].to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockSignature(u32, String);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileSignature {
+    pub block_size: u64,
+    pub file_len: u64,
+    pub blocks: Vec<BlockSignature>,
+}
+
+impl FileSignature {
+    pub fn compute(file: &File) -> Result<Self> {
+        let file_len = file.metadata()?.len();
+        let block_size = delta_block_size(file_len);
+        let mut reader = BufReader::new(file);
+        let mut buffer = vec![0; block_size as usize];
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset < file_len {
+            let size = min(block_size, file_len - offset) as usize;
+            reader.read_exact(&mut buffer[..size])?;
+            let weak = RollingChecksum::new(&buffer[..size]).digest();
+            blocks.push(BlockSignature(weak, strong_checksum(&buffer[..size])));
+            offset += size as u64;
+        }
+        Ok(Self {
+            block_size,
+            file_len,
+            blocks,
+        })
+    }
+
+    /// A signature from the peer must have the blocks this side computes for its file length
+    fn check(&self) -> Result<()> {
+        if self.block_size != delta_block_size(self.file_len)
+            || self.blocks.len() as u64 != self.file_len.div_ceil(self.block_size)
+        {
+            return Err(anyhow!(
+                "delta signature invalid, block_size={}, file_len={}, blocks={}",
+                self.block_size,
+                self.file_len,
+                self.blocks.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn block_len(&self, index: u64) -> Result<u64> {
+        let offset = index
+            .checked_mul(self.block_size)
+            .ok_or(anyhow!("delta block out of range, index={index}"))?;
+        Ok(min(self.block_size, self.file_len.saturating_sub(offset)))
+    }
+}
+
+/// Copies blocks of the destination file, or takes literal data from the source file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum DeltaOp {
+    Copy(u64, u64),
+    Literal(u64, u64),
+}
+
+#[derive(Default)]
+struct DeltaOps(Vec<DeltaOp>);
+
+impl DeltaOps {
+    fn copy(&mut self, index: u64) {
+        match self.0.last_mut() {
+            Some(DeltaOp::Copy(start, count)) if *start + *count == index => *count += 1,
+            _ => self.0.push(DeltaOp::Copy(index, 1)),
+        }
+    }
+
+    fn literal(&mut self, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(DeltaOp::Literal(start, size)) if *start + *size == offset => *size += len,
+            _ => self.0.push(DeltaOp::Literal(offset, len)),
+        }
+    }
+}
+
+/// A sliding view over a file, so the whole file is never held in memory
+struct FileWindow<'a> {
+    file: &'a File,
+    file_len: u64,
+    start: u64,
+    buffer: Vec<u8>,
+}
+
+impl<'a> FileWindow<'a> {
+    fn new(file: &'a File, file_len: u64) -> Self {
+        Self {
+            file,
+            file_len,
+            start: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn slice(&mut self, offset: u64, len: usize) -> Result<&[u8]> {
+        let end = offset + len as u64;
+        let buffer_end = self.start + self.buffer.len() as u64;
+        if offset < self.start || end > buffer_end {
+            // drop the data before offset, and read ahead
+            if offset < self.start || offset > buffer_end {
+                self.buffer.clear();
+            } else {
+                self.buffer.drain(..(offset - self.start) as usize);
+            }
+            self.start = offset;
+            let read_end = min(end + WINDOW_READ_SIZE as u64, self.file_len);
+            let read_from = self.start + self.buffer.len() as u64;
+            let mut data = vec![0; read_end.saturating_sub(read_from) as usize];
+            self.file.read_exact_at(&mut data, read_from)?;
+            self.buffer.extend(data);
+        }
+        let from = (offset - self.start) as usize;
+        Ok(&self.buffer[from..from + len])
+    }
+}
+
+pub fn compute_delta(file: &File, signature: &FileSignature) -> Result<Vec<DeltaOp>> {
+    let file_len = file.metadata()?.len();
+    signature.check()?;
+    let block_size = signature.block_size;
+
+    // index the full blocks by weak checksum
+    let mut weak_blocks: HashMap<u32, Vec<u64>> = HashMap::new();
+    for (index, block) in signature.blocks.iter().enumerate() {
+        if signature.block_len(index as u64)? == block_size {
+            weak_blocks.entry(block.0).or_default().push(index as u64);
+        }
+    }
+
+    let mut window = FileWindow::new(file, file_len);
+    let mut ops = DeltaOps::default();
+    let mut literal_start = 0;
+    let mut offset = 0;
+    let mut rolling = None;
+    while offset + block_size <= file_len {
+        let checksum = match rolling.take() {
+            Some(checksum) => checksum,
+            None => RollingChecksum::new(window.slice(offset, block_size as usize)?),
+        };
+
+        // look for a matched block
+        if let Some(indexes) = weak_blocks.get(&checksum.digest()) {
+            let strong = strong_checksum(window.slice(offset, block_size as usize)?);
+            if let Some(index) = indexes
+                .iter()
+                .find(|i| signature.blocks[**i as usize].1 == strong)
+            {
+                ops.literal(literal_start, offset - literal_start);
+                ops.copy(*index);
+                offset += block_size;
+                literal_start = offset;
+                continue;
+            }
+        }
+
+        // roll to the next byte
+        if offset + block_size < file_len {
+            let data = window.slice(offset, block_size as usize + 1)?;
+            let mut checksum = checksum;
+            checksum.roll(data[0], data[block_size as usize]);
+            rolling = Some(checksum);
+        }
+        offset += 1;
+    }
+
+    // the tail may still match the last partial block
+    let tail_len = file_len - literal_start;
+    if let Some(last_block) = signature.blocks.last() {
+        let last_index = signature.blocks.len() as u64 - 1;
+        let last_block_len = signature.block_len(last_index)?;
+        if last_block_len < block_size && last_block_len > 0 {
+            let tail_offset = file_len - last_block_len;
+            if tail_offset >= literal_start {
+                let data = window.slice(tail_offset, last_block_len as usize)?;
+                if RollingChecksum::new(data).digest() == last_block.0
+                    && strong_checksum(data) == last_block.1
+                {
+                    ops.literal(literal_start, tail_offset - literal_start);
+                    ops.copy(last_index);
+                    return Ok(fit_ops(ops.0, file_len));
+                }
+            }
+        }
+    }
+    ops.literal(literal_start, tail_len);
+    Ok(fit_ops(ops.0, file_len))
+}
+
+/// Too many ops do not fit in a payload, send the whole file as literal data then
+fn fit_ops(ops: Vec<DeltaOp>, file_len: u64) -> Vec<DeltaOp> {
+    if ops.len() as u64 > MAX_DELTA_BLOCKS {
+        return vec![DeltaOp::Literal(0, file_len)];
+    }
+    ops
+}
+
+pub fn literal_size(ops: &[DeltaOp]) -> u64 {
+    ops.iter()
+        .map(|op| match op {
+            DeltaOp::Copy(..) => 0,
+            DeltaOp::Literal(_, len) => *len,
+        })
+        .sum()
+}
+
+pub async fn send_literals(
+    file: &File,
+    ops: &[DeltaOp],
+    ss: &mut quinn::SendStream,
+    limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    for op in ops {
+        if let DeltaOp::Literal(offset, len) = op {
+            FileRange::new(file, *offset, *len)
+                .send_to(ss, limiter)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds the source file into `target` from the blocks of `basis` and the literal data
+pub async fn apply_delta<R: AsyncRead + Unpin>(
+    basis: &File,
+    block_size: u64,
+    target: &File,
+    ops: &[DeltaOp],
+    rs: &mut R,
+    limiter: Option<&RateLimiter>,
+) -> Result<u64> {
+    // the blocks must be the ones of the basis signature
+    let basis_len = basis.metadata()?.len();
+    if block_size != delta_block_size(basis_len) {
+        return Err(anyhow!(
+            "delta block size mismatch, size={block_size}, basis size={}",
+            delta_block_size(basis_len)
+        ));
+    }
+
+    let mut target_offset: u64 = 0;
+    for op in ops {
+        let len = match op {
+            DeltaOp::Copy(index, count) => {
+                let offset = index
+                    .checked_mul(block_size)
+                    .filter(|offset| *offset < basis_len)
+                    .ok_or(anyhow!("delta block out of range, index={index}"))?;
+                let len = count
+                    .checked_mul(block_size)
+                    .ok_or(anyhow!("delta block count out of range, count={count}"))?;
+                let len = min(len, basis_len - offset);
+                copy_range(basis, offset, len, target, target_offset)?;
+                len
+            }
+            DeltaOp::Literal(_, len) => {
+                FileRange::new(target, target_offset, *len)
+                    .recv_from(rs, limiter)
+                    .await?;
+                *len
+            }
+        };
+        target_offset = target_offset
+            .checked_add(len)
+            .ok_or(anyhow!("delta target too large"))?;
+    }
+    target.set_len(target_offset)?;
+    Ok(target_offset)
+}
+
+fn copy_range(from: &File, offset: u64, len: u64, to: &File, to_offset: u64) -> Result<()> {
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut copied = 0;
+    while copied < len {
+        let size = min(COPY_BUFFER_SIZE as u64, len - copied) as usize;
+        from.read_exact_at(&mut buffer[..size], offset + copied)?;
+        to.write_all_at(&buffer[..size], to_offset + copied)?;
+        copied += size as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Bytes that do not repeat within a block, so each block matches only itself
+    fn test_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn test_file(name: &str, data: &[u8]) -> (PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("lant-test-{}-{name}", std::process::id()));
+        fs::write(&path, data).unwrap();
+        let file = File::open(&path).unwrap();
+        (path, file)
+    }
+
+    #[test]
+    fn compute_delta_copies_a_same_file_with_its_tail() {
+        let data = test_data(2 * MIN_DELTA_BLOCK_SIZE as usize + 500, 1);
+        let (path, file) = test_file("delta-same", &data);
+        let signature = FileSignature::compute(&file).unwrap();
+        let ops = compute_delta(&file, &signature).unwrap();
+        assert!(matches!(ops.as_slice(), [DeltaOp::Copy(0, 3)]));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compute_delta_refuses_a_forged_signature() {
+        let data = test_data(2 * MIN_DELTA_BLOCK_SIZE as usize, 1);
+        let (path, file) = test_file("delta-forged", &data);
+        let mut signature = FileSignature::compute(&file).unwrap();
+        signature.block_size = u64::MAX;
+        assert!(compute_delta(&file, &signature).is_err());
+        signature.block_size = MIN_DELTA_BLOCK_SIZE;
+        signature.blocks.pop();
+        assert!(compute_delta(&file, &signature).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compute_delta_matches_the_tail_after_new_data() {
+        let basis = test_data(2 * MIN_DELTA_BLOCK_SIZE as usize + 500, 1);
+        let (basis_path, basis_file) = test_file("delta-tail-basis", &basis);
+        let mut source = test_data(100, 2);
+        source.extend(&basis);
+        let (source_path, source_file) = test_file("delta-tail-source", &source);
+        let signature = FileSignature::compute(&basis_file).unwrap();
+        let ops = compute_delta(&source_file, &signature).unwrap();
+        assert!(matches!(
+            ops.as_slice(),
+            [DeltaOp::Literal(0, 100), DeltaOp::Copy(0, 3)]
+        ));
+        fs::remove_file(basis_path).unwrap();
+        fs::remove_file(source_path).unwrap();
+    }
+
+    #[test]
+    fn fit_ops_falls_back_to_the_whole_file() {
+        let ops = (0..MAX_DELTA_BLOCKS)
+            .map(|index| DeltaOp::Copy(index * 2, 1))
+            .collect::<Vec<_>>();
+        assert_eq!(fit_ops(ops.clone(), 100).len(), ops.len());
+        let mut ops = ops;
+        ops.push(DeltaOp::Literal(0, 1));
+        assert!(matches!(
+            fit_ops(ops, 100).as_slice(),
+            [DeltaOp::Literal(0, 100)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn apply_delta_rebuilds_the_source() {
+        let dir = std::env::temp_dir().join(format!("lant-test-{}-delta", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // the source changes a block of the basis, drops another and grows at the end
+        let basis = test_data(64 * 1024, 1);
+        let mut source = basis.clone();
+        source[10_000..10_100].copy_from_slice(&test_data(100, 2));
+        source.drain(30_000..33_000);
+        source.extend(test_data(5_000, 3));
+        fs::write(dir.join("basis"), &basis).unwrap();
+        fs::write(dir.join("source"), &source).unwrap();
+
+        let basis_file = File::open(dir.join("basis")).unwrap();
+        let signature = FileSignature::compute(&basis_file).unwrap();
+        let ops = compute_delta(&File::open(dir.join("source")).unwrap(), &signature).unwrap();
+        assert!(ops.iter().any(|op| matches!(op, DeltaOp::Copy(..))));
+        assert!(literal_size(&ops) < source.len() as u64);
+
+        // the literal data is streamed in op order
+        let literals = ops
+            .iter()
+            .filter_map(|op| match op {
+                DeltaOp::Literal(offset, len) => {
+                    Some(&source[*offset as usize..(offset + len) as usize])
+                }
+                DeltaOp::Copy(..) => None,
+            })
+            .collect::<Vec<_>>()
+            .concat();
+        let target = File::options()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(dir.join("target"))
+            .unwrap();
+        let len = apply_delta(
+            &basis_file,
+            signature.block_size,
+            &target,
+            &ops,
+            &mut literals.as_slice(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(len, source.len() as u64);
+        assert_eq!(fs::read(dir.join("target")).unwrap(), source);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn apply_delta_refuses_blocks_out_of_range() {
+        let dir =
+            std::env::temp_dir().join(format!("lant-test-{}-delta-range", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("basis"), test_data(4096, 1)).unwrap();
+        let basis_file = File::open(dir.join("basis")).unwrap();
+        let target = File::create(dir.join("target")).unwrap();
+
+        for ops in [[DeltaOp::Copy(2, 1)], [DeltaOp::Copy(u64::MAX, 1)]] {
+            let result =
+                apply_delta(&basis_file, 2048, &target, &ops, &mut [].as_slice(), None).await;
+            assert!(result.is_err());
+        }
+
+        // a count that overflows, and a block size the basis would not have
+        let ops = [DeltaOp::Copy(0, u64::MAX)];
+        let result = apply_delta(&basis_file, 2048, &target, &ops, &mut [].as_slice(), None).await;
+        assert!(result.is_err());
+        let ops = [DeltaOp::Copy(0, 1)];
+        let result = apply_delta(&basis_file, 1024, &target, &ops, &mut [].as_slice(), None).await;
+        assert!(result.is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,25 +1,75 @@
-use crate::utils::dir::DirItemType::{Dir, File};
+use crate::cli::LinkPolicy;
+use crate::utils::dir::DirItemType::{Dir, File, Other, Symlink};
+use anyhow::{anyhow, Result};
+use path_absolutize::Absolutize;
 use serde::{Deserialize, Serialize};
-use std::fs::FileType;
+use std::fs;
+use std::fs::{FileType, Metadata};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
-#[derive(Serialize, Deserialize, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
 pub enum DirItemType {
     Dir,
     File,
+    Symlink,
+    /// Sockets, fifos and devices
+    Other,
+}
+
+impl DirItemType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dir => "dir",
+            File => "file",
+            Symlink => "link",
+            Other => "other",
+        }
+    }
 }
 
 impl From<FileType> for DirItemType {
     fn from(item_type: FileType) -> Self {
-        if item_type.is_file() { File } else { Dir }
+        if item_type.is_symlink() {
+            Symlink
+        } else if item_type.is_dir() {
+            Dir
+        } else if item_type.is_file() {
+            File
+        } else {
+            Other
+        }
     }
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct DirItem(String, DirItemType);
+pub struct DirItem(
+    String,
+    DirItemType,
+    #[serde(default)] u64,
+    #[serde(default)] u64,
+    #[serde(default)] Option<String>,
+    #[serde(default)] u32,
+);
 
 impl DirItem {
-    pub fn new(name: impl Into<String>, item_type: DirItemType) -> Self {
-        Self(name.into(), item_type)
+    /// The metadata of a link is its own, not the one of its target
+    pub fn from_metadata(name: impl Into<String>, meta: &Metadata) -> Self {
+        Self(
+            name.into(),
+            DirItemType::from(meta.file_type()),
+            meta.len(),
+            mtime_secs(meta),
+            None,
+            meta.permissions().mode() & 0o7777,
+        )
+    }
+
+    pub fn from_link(name: impl Into<String>, meta: &Metadata, target: &Path) -> Self {
+        let mut item = Self::from_metadata(name, meta);
+        item.4 = Some(target.to_string_lossy().to_string());
+        item
     }
 
     pub fn name(&self) -> String {
@@ -29,4 +79,390 @@ impl DirItem {
     pub fn is_file(&self) -> bool {
         self.1 == File
     }
+
+    pub fn is_dir(&self) -> bool {
+        self.1 == Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.1 == Symlink
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.1.name()
+    }
+
+    pub fn link_target(&self) -> Option<&str> {
+        self.4.as_deref()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.2
+    }
+
+    /// Seconds since the unix epoch
+    pub fn mtime(&self) -> u64 {
+        self.3
+    }
+
+    /// Permission bits, with setuid, setgid and sticky
+    pub fn mode(&self) -> u32 {
+        self.5
+    }
+}
+
+/// Permission bits as `ls` shows them, with setuid, setgid and sticky
+pub fn permissions_string(mode: u32) -> String {
+    let special = [(0o4000, 's', 'S'), (0o2000, 's', 'S'), (0o1000, 't', 'T')];
+    let mut permissions = String::with_capacity(9);
+    for (i, (special_bit, set, unset)) in special.into_iter().enumerate() {
+        let bits = mode >> (6 - i * 3);
+        permissions.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        permissions.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        permissions.push(match (bits & 0o1 != 0, mode & special_bit != 0) {
+            (true, true) => set,
+            (false, true) => unset,
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    permissions
+}
+
+pub fn mtime_secs(meta: &Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |mtime| mtime.as_secs())
+}
+
+/// Files and dirs under the dir, a dir comes before its entries, a followed link to an ancestor
+/// dir is not walked again
+pub fn walk_files(dir: &Path, links: LinkPolicy) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut ancestors = vec![dir.canonicalize()?];
+    walk_files_into(dir, Path::new(""), links, &mut ancestors, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn walk_files_into(
+    dir: &Path,
+    rel_dir: &Path,
+    links: LinkPolicy,
+    ancestors: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let rel_path = rel_dir.join(entry.file_name());
+        let mut entry_type = entry.file_type()?;
+        if entry_type.is_symlink() {
+            match links {
+                LinkPolicy::Skip => continue,
+                LinkPolicy::CopyAsLink => {
+                    files.push(rel_path);
+                    continue;
+                }
+                LinkPolicy::Follow => match fs::metadata(entry.path()) {
+                    Ok(meta) => entry_type = meta.file_type(),
+                    // a dangling link
+                    Err(_) => continue,
+                },
+            }
+        }
+        if entry_type.is_dir() {
+            let abs_dir = entry.path().canonicalize()?;
+            if ancestors.contains(&abs_dir) {
+                continue;
+            }
+            ancestors.push(abs_dir);
+            files.push(rel_path.clone());
+            walk_files_into(&entry.path(), &rel_path, links, ancestors, files)?;
+            ancestors.pop();
+        } else if entry_type.is_file() {
+            files.push(rel_path);
+        }
+    }
+    Ok(())
+}
+
+pub fn join_in_root(abs_root_dir: &Path, path: &Path) -> Result<PathBuf> {
+    let joined = abs_root_dir.join(path);
+    let joined = joined.absolutize()?;
+    if joined.starts_with(abs_root_dir) {
+        Ok(joined.to_path_buf())
+    } else {
+        Ok(abs_root_dir.to_path_buf())
+    }
+}
+
+/// The path is resolved through its parent only, so a link is taken as itself rather than its target
+pub fn entry_in_root(abs_root_dir: &Path, path: &Path) -> Result<PathBuf> {
+    if !abs_root_dir
+        .join(path)
+        .absolutize()?
+        .starts_with(abs_root_dir)
+    {
+        return Err(anyhow!("path outside root, path={path:?}"));
+    }
+    let abs_path = join_in_root(abs_root_dir, path)?;
+    if abs_path == abs_root_dir {
+        return Ok(abs_path);
+    }
+    let (Some(abs_parent), Some(name)) = (abs_path.parent(), abs_path.file_name()) else {
+        return Ok(abs_path);
+    };
+    let abs_parent = abs_parent.canonicalize()?;
+    if !abs_parent.starts_with(abs_root_dir) {
+        return Err(anyhow!("path outside root, path={path:?}"));
+    }
+    Ok(abs_parent.join(name))
+}
+
+/// Dirs are made one by one, and an existing one must not lead out of root through a link, the
+/// resolved path of the last one is returned
+pub fn make_dirs_in_root(abs_root_dir: &Path, abs_path: &Path, parents: bool) -> Result<PathBuf> {
+    let rel_path = abs_path.strip_prefix(abs_root_dir)?;
+    let mut curr_dir = abs_root_dir.to_path_buf();
+    let mut components = rel_path.components().peekable();
+    while let Some(component) = components.next() {
+        let is_last = components.peek().is_none();
+        let next_dir = curr_dir.join(component);
+        if fs::symlink_metadata(&next_dir).is_err() {
+            if !is_last && !parents {
+                return Err(anyhow!("parent dir not exists"));
+            }
+            fs::create_dir(&next_dir)?;
+            curr_dir = next_dir;
+            continue;
+        }
+        if is_last && !parents {
+            return Err(anyhow!("dir already exists"));
+        }
+        curr_dir = next_dir.canonicalize()?;
+        if !curr_dir.starts_with(abs_root_dir) {
+            return Err(anyhow!("path leads out of root"));
+        }
+        if !curr_dir.is_dir() {
+            return Err(anyhow!("path exists and is not a dir"));
+        }
+    }
+    Ok(curr_dir)
+}
+
+/// A link target must stay inside root, both as written and as resolved now, a dangling target is
+/// refused as it could resolve anywhere once the missing part shows up
+pub fn link_in_root(abs_root_dir: &Path, abs_link_path: &Path, target: &Path) -> bool {
+    let Some(link_dir) = abs_link_path.parent() else {
+        return false;
+    };
+    let abs_target = link_dir.join(target);
+    let lexical_in_root = !target.is_absolute()
+        && abs_target
+            .absolutize()
+            .is_ok_and(|path| path.starts_with(abs_root_dir));
+    lexical_in_root
+        && abs_target
+            .canonicalize()
+            .is_ok_and(|path| path.starts_with(abs_root_dir))
+}
+
+/// Links inside root may be changed after they are made, so a path is resolved again each time
+/// data is written through it
+pub fn resolve_in_root(abs_root_dir: &Path, abs_path: &Path) -> Result<()> {
+    let Ok(meta) = fs::symlink_metadata(abs_path) else {
+        return Ok(());
+    };
+    if meta.is_symlink()
+        && !abs_path
+            .canonicalize()
+            .is_ok_and(|path| path.starts_with(abs_root_dir))
+    {
+        return Err(anyhow!("link resolves outside root, path={abs_path:?}"));
+    }
+    Ok(())
+}
+
+/// The first `stem (n).ext` beside the file that does not exist yet
+pub fn unique_file_path(file_path: &Path) -> PathBuf {
+    let stem = file_path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| file_path.with_file_name(format!("{stem} ({n}){extension}")))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+pub fn is_single_component(name: &Path) -> bool {
+    let mut components = name.components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+/// A relative path made of names only, without root, `.` or `..`
+pub fn is_normal_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// A fresh dir holding `root` and a sibling `outside`, the canonical root is returned
+    fn test_root(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("lant-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("root")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        base.join("root").canonicalize().unwrap()
+    }
+
+    fn remove_test_root(root: &Path) {
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn join_in_root_falls_back_to_root() {
+        let root = Path::new("/srv/root");
+        assert_eq!(
+            join_in_root(root, Path::new("a/b")).unwrap(),
+            root.join("a/b")
+        );
+        assert_eq!(
+            join_in_root(root, Path::new("a/../b")).unwrap(),
+            root.join("b")
+        );
+        assert_eq!(join_in_root(root, Path::new("../x")).unwrap(), root);
+        assert_eq!(join_in_root(root, Path::new("a/../../x")).unwrap(), root);
+    }
+
+    #[test]
+    fn entry_in_root_keeps_a_link_as_itself() {
+        let root = test_root("entry");
+        symlink(root.parent().unwrap().join("outside"), root.join("out")).unwrap();
+        assert_eq!(entry_in_root(&root, Path::new(".")).unwrap(), root);
+        assert_eq!(
+            entry_in_root(&root, Path::new("out")).unwrap(),
+            root.join("out")
+        );
+        assert!(entry_in_root(&root, Path::new("out/f")).is_err());
+        assert!(entry_in_root(&root, Path::new("../x")).is_err());
+        remove_test_root(&root);
+    }
+
+    #[test]
+    fn link_in_root_needs_a_resolvable_target_inside_root() {
+        let root = test_root("link");
+        fs::create_dir(root.join("d")).unwrap();
+        symlink(".", root.join("self")).unwrap();
+        symlink(root.parent().unwrap().join("outside"), root.join("out")).unwrap();
+        let link_path = root.join("l");
+        assert!(link_in_root(&root, &link_path, Path::new("d")));
+        assert!(link_in_root(&root, &link_path, Path::new(".")));
+        assert!(!link_in_root(&root, &link_path, Path::new("/tmp")));
+        assert!(!link_in_root(&root, &link_path, Path::new("..")));
+        assert!(!link_in_root(&root, &link_path, Path::new("missing")));
+        assert!(!link_in_root(&root, &link_path, Path::new("s/b/..")));
+        assert!(!link_in_root(&root, &link_path, Path::new("out")));
+        assert!(!link_in_root(&root, &link_path, Path::new("self/..")));
+        remove_test_root(&root);
+    }
+
+    #[test]
+    fn resolve_in_root_refuses_links_leading_out() {
+        let root = test_root("resolve");
+        fs::write(root.join("f"), b"f").unwrap();
+        symlink("f", root.join("in")).unwrap();
+        symlink(root.parent().unwrap().join("outside"), root.join("out")).unwrap();
+        symlink("missing", root.join("dangling")).unwrap();
+        assert!(resolve_in_root(&root, &root.join("f")).is_ok());
+        assert!(resolve_in_root(&root, &root.join("new")).is_ok());
+        assert!(resolve_in_root(&root, &root.join("in")).is_ok());
+        assert!(resolve_in_root(&root, &root.join("out")).is_err());
+        assert!(resolve_in_root(&root, &root.join("dangling")).is_err());
+        remove_test_root(&root);
+    }
+
+    #[test]
+    fn make_dirs_in_root_stops_at_links_leading_out() {
+        let root = test_root("make");
+        symlink(root.parent().unwrap().join("outside"), root.join("out")).unwrap();
+        let made = make_dirs_in_root(&root, &root.join("a/b"), true).unwrap();
+        assert_eq!(made, root.join("a/b"));
+        assert!(make_dirs_in_root(&root, &root.join("a/b"), false).is_err());
+        assert!(make_dirs_in_root(&root, &root.join("x/y"), false).is_err());
+        assert!(make_dirs_in_root(&root, &root.join("out/x"), true).is_err());
+        assert!(!root.parent().unwrap().join("outside/x").exists());
+        remove_test_root(&root);
+    }
+
+    #[test]
+    fn permissions_string_shows_special_bits() {
+        assert_eq!(permissions_string(0o755), "rwxr-xr-x");
+        assert_eq!(permissions_string(0o100644), "rw-r--r--");
+        assert_eq!(permissions_string(0o4755), "rwsr-xr-x");
+        assert_eq!(permissions_string(0o4644), "rwSr--r--");
+        assert_eq!(permissions_string(0o2750), "rwxr-s---");
+        assert_eq!(permissions_string(0o1777), "rwxrwxrwt");
+        assert_eq!(permissions_string(0o1776), "rwxrwxrwT");
+        assert_eq!(permissions_string(0), "---------");
+    }
+
+    #[test]
+    fn unique_file_path_numbers_the_name() {
+        let root = test_root("unique");
+        fs::write(root.join("a.txt"), b"a").unwrap();
+        assert_eq!(
+            unique_file_path(&root.join("a.txt")),
+            root.join("a (1).txt")
+        );
+        fs::write(root.join("a (1).txt"), b"a").unwrap();
+        assert_eq!(
+            unique_file_path(&root.join("a.txt")),
+            root.join("a (2).txt")
+        );
+        fs::write(root.join("b"), b"b").unwrap();
+        assert_eq!(unique_file_path(&root.join("b")), root.join("b (1)"));
+        assert_eq!(
+            unique_file_path(&root.join("c.tar.gz")),
+            root.join("c.tar (1).gz")
+        );
+        remove_test_root(&root);
+    }
+
+    #[test]
+    fn is_normal_path_takes_names_only() {
+        assert!(is_normal_path(Path::new("a")));
+        assert!(is_normal_path(Path::new("a/b")));
+        assert!(!is_normal_path(Path::new("")));
+        assert!(!is_normal_path(Path::new("/a")));
+        assert!(!is_normal_path(Path::new("../a")));
+        assert!(!is_normal_path(Path::new("a/../b")));
+    }
+
+    #[test]
+    fn walk_files_lists_empty_dirs() {
+        let root = test_root("walk");
+        fs::create_dir_all(root.join("a/empty")).unwrap();
+        fs::write(root.join("a/f"), b"f").unwrap();
+        assert_eq!(
+            walk_files(&root, LinkPolicy::Skip).unwrap(),
+            vec![
+                PathBuf::from("a"),
+                PathBuf::from("a/empty"),
+                PathBuf::from("a/f")
+            ]
+        );
+        remove_test_root(&root);
+    }
 }
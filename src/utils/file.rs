@@ -1,17 +1,203 @@
 use std::cmp::min;
+use std::ffi::OsString;
 use std::fs;
-use std::path::Path;
+use std::fs::{File, Metadata, Permissions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
+use anyhow::{anyhow, Error, Result};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-const FILE_CHUNK_UNIT_SIZE: usize = 4096 * 4096;
+pub const DEFAULT_CHUNK_UNIT_SIZE: usize = 4096 * 4096;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Default, Debug)]
-pub struct FileChunkSize(u64, usize);
+pub const MAX_CHUNK_RETRIES: usize = 3;
+
+pub const PART_FILE_SUFFIX: &str = ".lant-part";
+
+pub const PART_STATE_SUFFIX: &str = ".lant-part-state";
+
+/// A range of the part file state, offset and length
+const SIZE_OF_PART_RANGE: usize = 2 * size_of::<u64>();
+
+/// The path that stands for stdin or stdout
+pub const STDIO_PATH: &str = "-";
+
+#[derive(Error, Debug)]
+pub(crate) enum ChunkError {
+    #[error("chunk checksum mismatch, index={0}")]
+    ChecksumMismatch(u64),
+}
+
+impl ChunkError {
+    pub fn is_checksum_mismatch(e: &Error) -> bool {
+        matches!(
+            e.downcast_ref::<ChunkError>(),
+            Some(ChunkError::ChecksumMismatch(_))
+        )
+    }
+}
+
+/// What a transfer does when the destination file already exists
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ExistingFilePolicy {
+    #[default]
+    Resume,
+    Overwrite,
+    SkipExisting,
+    Rename,
+    FailIfExists,
+}
+
+/// The hidden file incoming data is written to, it is renamed to the final name when complete
+pub fn part_file_path(file_path: &Path) -> Result<PathBuf> {
+    let file_name = file_path
+        .file_name()
+        .ok_or(anyhow!("got file name error"))?;
+    let mut part_name = OsString::from(".");
+    part_name.push(file_name);
+    part_name.push(PART_FILE_SUFFIX);
+    Ok(file_path.with_file_name(part_name))
+}
+
+pub fn is_part_file(file_path: &Path) -> bool {
+    file_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| {
+            name.starts_with('.')
+                && (name.ends_with(PART_FILE_SUFFIX) || name.ends_with(PART_STATE_SUFFIX))
+        })
+}
+
+/// The written ranges of a part file are kept beside it, chunks complete out of order so the
+/// part file length does not tell where to resume
+fn part_state_path(part_file_path: &Path) -> Result<PathBuf> {
+    let mut state_name = part_file_path
+        .file_name()
+        .ok_or(anyhow!("got file name error"))?
+        .to_os_string();
+    state_name.push("-state");
+    Ok(part_file_path.with_file_name(state_name))
+}
+
+/// Records a verified chunk, a single small append is not interleaved with a parallel one
+pub fn add_part_range(part_file_path: &Path, offset: u64, len: u64) -> Result<()> {
+    let mut range = [0; SIZE_OF_PART_RANGE];
+    range[..8].copy_from_slice(&offset.to_le_bytes());
+    range[8..].copy_from_slice(&len.to_le_bytes());
+    File::options()
+        .create(true)
+        .append(true)
+        .open(part_state_path(part_file_path)?)?
+        .write_all(&range)?;
+    Ok(())
+}
+
+/// The length of the part file written without a gap, from its start
+pub fn part_done_len(part_file_path: &Path) -> Result<u64> {
+    let state = match fs::read(part_state_path(part_file_path)?) {
+        Ok(state) => state,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    // an unfinished last range is left out
+    let mut ranges = state
+        .chunks_exact(SIZE_OF_PART_RANGE)
+        .map(|range| {
+            let (offset, len) = range.split_at(8);
+            (
+                u64::from_le_bytes(offset.try_into().unwrap_or_default()),
+                u64::from_le_bytes(len.try_into().unwrap_or_default()),
+            )
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+    let mut done_len = 0;
+    for (offset, len) in ranges {
+        if offset > done_len {
+            break;
+        }
+        done_len = done_len.max(offset.saturating_add(len));
+    }
+    let part_file_len = fs::metadata(part_file_path).map_or(0, |meta| meta.len());
+    Ok(done_len.min(part_file_len))
+}
+
+/// Drops the written ranges, when the part file restarts or is complete
+pub fn remove_part_state(part_file_path: &Path) -> Result<()> {
+    match fs::remove_file(part_state_path(part_file_path)?) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Modification time and Unix mode bits of a file, applied by the receiver when it completes
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct FileAttrs {
+    pub mtime_nanos: u64,
+    pub mode: u32,
+}
+
+impl FileAttrs {
+    pub fn new(mtime_nanos: u64, mode: u32) -> Self {
+        Self { mtime_nanos, mode }
+    }
+
+    pub fn from_metadata(meta: &Metadata) -> Self {
+        let mtime_nanos = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_nanos() as u64);
+        Self::new(mtime_nanos, meta.mode() & 0o7777)
+    }
+
+    /// Drops the setuid, setgid and sticky bits, a peer may only grant plain permissions
+    pub fn permission_only(self) -> Self {
+        Self::new(self.mtime_nanos, self.mode & 0o777)
+    }
+
+    pub fn apply(&self, file_path: &Path) -> Result<()> {
+        // the time first, the mode may take away write permission
+        File::open(file_path)?.set_modified(UNIX_EPOCH + Duration::from_nanos(self.mtime_nanos))?;
+        fs::set_permissions(file_path, Permissions::from_mode(self.mode))?;
+        Ok(())
+    }
+}
+
+pub fn default_chunk_unit_size() -> usize {
+    DEFAULT_CHUNK_UNIT_SIZE
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FileChunkSize(
+    u64,
+    usize,
+    #[serde(default = "default_chunk_unit_size")] usize,
+);
+
+impl Default for FileChunkSize {
+    fn default() -> Self {
+        Self(0, 0, DEFAULT_CHUNK_UNIT_SIZE)
+    }
+}
 
 impl FileChunkSize {
-    pub fn new(total_chunk: u64, rest_size: usize) -> Self {
-        Self(total_chunk, rest_size)
+    pub fn new(total_chunk: u64, rest_size: usize, chunk_unit_size: usize) -> Self {
+        Self(total_chunk, rest_size, chunk_unit_size)
+    }
+
+    pub fn from_len(len: usize, chunk_unit_size: usize) -> Self {
+        let mut total_chunks = (len / chunk_unit_size) as u64;
+        let rest_size = len % chunk_unit_size;
+        if rest_size != 0 {
+            total_chunks += 1;
+        }
+        Self(total_chunks, rest_size, chunk_unit_size)
     }
 
     pub fn total_size(&self) -> usize {
@@ -19,13 +205,17 @@ impl FileChunkSize {
     }
 
     pub fn integer_size(&self) -> usize {
-        self.integer_chunks() as usize * FILE_CHUNK_UNIT_SIZE
+        self.integer_chunks() as usize * self.2
     }
 
     pub fn rest_size(&self) -> usize {
         self.1
     }
 
+    pub fn chunk_unit_size(&self) -> usize {
+        self.2
+    }
+
     pub fn total_chunks(&self) -> u64 {
         self.0
     }
@@ -34,6 +224,11 @@ impl FileChunkSize {
         self.0 - if self.0 > 0 && self.1 > 0 { 1 } else { 0 }
     }
 
+    pub fn chunk_size(&self, index: u64) -> usize {
+        let offset = index_offset(index, self.2) as usize;
+        buffer_size(self.total_size().saturating_sub(offset), self.2)
+    }
+
     pub fn last_chunk_index(&self) -> u64 {
         self.0 - if self.0 > 0 { 1 } else { 0 }
     }
@@ -43,32 +238,131 @@ impl FileChunkSize {
         if last_chunk_index == 0 {
             self.rest_size()
         } else {
-            self.total_size() - (last_chunk_index - 1) as usize * FILE_CHUNK_UNIT_SIZE
+            self.total_size() - (last_chunk_index - 1) as usize * self.2
         }
     }
 }
 
-impl From<usize> for FileChunkSize {
-    fn from(len: usize) -> Self {
-        let mut total_chunks = (len / FILE_CHUNK_UNIT_SIZE) as u64;
-        let rest_size = len % FILE_CHUNK_UNIT_SIZE;
-        if rest_size != 0 {
-            total_chunks += 1;
-        }
-        Self(total_chunks, rest_size)
+pub fn get_file_chunk_size<P: AsRef<Path>>(file_path: P, chunk_unit_size: usize) -> FileChunkSize {
+    fs::metadata(&file_path).map_or(FileChunkSize::from_len(0, chunk_unit_size), |meta| {
+        FileChunkSize::from_len(meta.len() as usize, chunk_unit_size)
+    })
+}
+
+pub fn buffer_size(buffer_size: usize, chunk_unit_size: usize) -> usize {
+    min(buffer_size, chunk_unit_size)
+}
+
+pub fn index_offset(index: u64, chunk_unit_size: usize) -> u64 {
+    index * chunk_unit_size as u64
+}
+
+pub fn negotiate_chunk_unit_size(requested: usize, max_chunk_unit_size: usize) -> usize {
+    match requested {
+        0 => min(DEFAULT_CHUNK_UNIT_SIZE, max_chunk_unit_size),
+        requested => min(requested, max_chunk_unit_size),
     }
 }
 
-pub fn get_file_chunk_size<P: AsRef<Path>>(file_path: P) -> FileChunkSize {
-    fs::metadata(&file_path).map_or(Default::default(), |meta| {
-        FileChunkSize::from(meta.len() as usize)
-    })
+pub fn file_digest<P: AsRef<Path>>(file_path: P) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(file_path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
 }
 
-pub fn buffer_size(buffer_size: usize) -> usize {
-    min(buffer_size, FILE_CHUNK_UNIT_SIZE)
+pub fn file_prefix_digest<P: AsRef<Path>>(file_path: P, prefix_len: u64) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(file_path)?.take(prefix_len))?;
+    Ok(hasher.finalize().to_hex().to_string())
 }
 
-pub fn index_offset(index: u64) -> u64 {
-    index * FILE_CHUNK_UNIT_SIZE as u64
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_len_counts_the_rest_as_a_chunk() {
+        let chunk_size = FileChunkSize::from_len(10, 4);
+        assert_eq!(chunk_size.total_chunks(), 3);
+        assert_eq!(chunk_size.integer_chunks(), 2);
+        assert_eq!(chunk_size.rest_size(), 2);
+        assert_eq!(chunk_size.total_size(), 10);
+        assert_eq!(chunk_size.last_chunk_index(), 2);
+        assert_eq!(chunk_size.chunk_size(0), 4);
+        assert_eq!(chunk_size.chunk_size(2), 2);
+        assert_eq!(chunk_size.chunk_size(3), 0);
+    }
+
+    #[test]
+    fn from_len_of_whole_chunks() {
+        let chunk_size = FileChunkSize::from_len(8, 4);
+        assert_eq!(chunk_size, FileChunkSize::new(2, 0, 4));
+        assert_eq!(chunk_size.integer_chunks(), 2);
+        assert_eq!(chunk_size.total_size(), 8);
+        assert_eq!(chunk_size.chunk_size(1), 4);
+    }
+
+    #[test]
+    fn from_len_of_empty_file() {
+        let chunk_size = FileChunkSize::from_len(0, 4);
+        assert_eq!(chunk_size, FileChunkSize::new(0, 0, 4));
+        assert_eq!(chunk_size.integer_chunks(), 0);
+        assert_eq!(chunk_size.last_chunk_index(), 0);
+        assert_eq!(chunk_size.chunk_size(0), 0);
+    }
+
+    #[test]
+    fn checksum_mismatch_is_told_apart_for_a_retry() {
+        let e: Error = ChunkError::ChecksumMismatch(3).into();
+        assert!(ChunkError::is_checksum_mismatch(&e));
+        assert!(ChunkError::is_checksum_mismatch(
+            &e.context("get chunk failed")
+        ));
+        assert!(!ChunkError::is_checksum_mismatch(&anyhow::anyhow!(
+            "other error"
+        )));
+    }
+
+    #[test]
+    fn permission_only_drops_special_bits() {
+        let attrs = FileAttrs::new(7, 0o6755).permission_only();
+        assert_eq!(attrs, FileAttrs::new(7, 0o755));
+    }
+
+    #[test]
+    fn part_done_len_stops_at_the_first_gap() {
+        let part_file_path = std::env::temp_dir().join(format!(
+            ".lant-test-{}-done{PART_FILE_SUFFIX}",
+            std::process::id()
+        ));
+        fs::write(&part_file_path, [1; 10]).unwrap();
+        remove_part_state(&part_file_path).unwrap();
+        assert_eq!(part_done_len(&part_file_path).unwrap(), 0);
+
+        // chunks 0 and 2 done, 1 still missing
+        add_part_range(&part_file_path, 8, 4).unwrap();
+        add_part_range(&part_file_path, 0, 4).unwrap();
+        assert_eq!(part_done_len(&part_file_path).unwrap(), 4);
+        assert!(is_part_file(&part_state_path(&part_file_path).unwrap()));
+
+        // the last range is capped by the file length
+        add_part_range(&part_file_path, 4, 4).unwrap();
+        assert_eq!(part_done_len(&part_file_path).unwrap(), 10);
+
+        remove_part_state(&part_file_path).unwrap();
+        assert_eq!(part_done_len(&part_file_path).unwrap(), 0);
+        fs::remove_file(&part_file_path).unwrap();
+    }
+
+    #[test]
+    fn negotiate_chunk_unit_size_is_capped_by_the_server() {
+        let max = 1024 * 1024;
+        assert_eq!(
+            negotiate_chunk_unit_size(0, usize::MAX),
+            DEFAULT_CHUNK_UNIT_SIZE
+        );
+        assert_eq!(negotiate_chunk_unit_size(0, max), max);
+        assert_eq!(negotiate_chunk_unit_size(4096, max), 4096);
+        assert_eq!(negotiate_chunk_unit_size(max * 2, max), max);
+    }
 }
@@ -1,5 +1,12 @@
 pub mod bytes_num;
+pub mod codec;
 pub mod cursor;
+pub mod delta;
 pub mod dir;
 pub mod file;
 pub mod json;
+pub mod progress;
+pub mod rate;
+pub mod sparse;
+pub mod state;
+pub mod stream;
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::IsTerminal;
+use std::time::{Duration, Instant};
+
+const SIZED_TEMPLATE: &str =
+    "{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} avg {avg_rate} ETA {eta}";
+
+const UNSIZED_TEMPLATE: &str = "{msg} {bytes} {bytes_per_sec} avg {avg_rate} {elapsed}";
+
+/// Progress is only drawn for someone watching stdout, chunk level logs are printed otherwise
+pub fn is_interactive() -> bool {
+    std::io::stdout().is_terminal()
+}
+
+/// Progress of the file being transferred, and the totals of the whole command
+pub struct Progress {
+    bar: RefCell<ProgressBar>,
+    bar_start: Cell<u64>,
+    started: Instant,
+    files: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self {
+            bar: RefCell::new(ProgressBar::hidden()),
+            bar_start: Cell::new(0),
+            started: Instant::now(),
+            files: Cell::new(0),
+            bytes: Cell::new(0),
+        }
+    }
+
+    /// The bar starts at the data a resumed file already holds, the average rate leaves it out
+    pub fn start_file(&self, name: &str, len: Option<u64>, pos: u64) -> Result<()> {
+        if !is_interactive() {
+            return Ok(());
+        }
+        let template = if len.is_some() {
+            SIZED_TEMPLATE
+        } else {
+            UNSIZED_TEMPLATE
+        };
+        let style = ProgressStyle::with_template(template)?
+            .progress_chars("=> ")
+            .with_key(
+                "avg_rate",
+                move |state: &ProgressState, w: &mut dyn fmt::Write| {
+                    let rate = average_rate(state.pos() - pos, state.elapsed());
+                    let _ = write!(w, "{}/s", HumanBytes(rate));
+                },
+            );
+        let bar = ProgressBar::with_draw_target(len, ProgressDrawTarget::stdout())
+            .with_style(style)
+            .with_message(name.to_string())
+            .with_position(pos);
+        self.bar.replace(bar);
+        self.bar_start.set(pos);
+        Ok(())
+    }
+
+    pub fn inc(&self, bytes: u64) {
+        self.bar.borrow().inc(bytes);
+        self.bytes.set(self.bytes.get() + bytes);
+    }
+
+    /// The bar is replaced by a line with the final numbers of the file
+    pub fn finish_file(&self) {
+        let bar = self.bar.replace(ProgressBar::hidden());
+        if !bar.is_hidden() {
+            bar.finish_and_clear();
+            let elapsed = bar.elapsed();
+            println!(
+                "{}: {} in {:.1}s, avg {}/s",
+                bar.message(),
+                HumanBytes(bar.position()),
+                elapsed.as_secs_f64(),
+                HumanBytes(average_rate(bar.position() - self.bar_start.get(), elapsed)),
+            );
+        }
+        self.files.set(self.files.get() + 1);
+    }
+
+    pub fn println(&self, message: &str) {
+        let bar = self.bar.borrow();
+        if bar.is_hidden() {
+            println!("{message}");
+        } else {
+            bar.println(message);
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let elapsed = self.started.elapsed();
+        format!(
+            "{} file(s), {} in {:.1}s, avg {}/s",
+            self.files.get(),
+            HumanBytes(self.bytes.get()),
+            elapsed.as_secs_f64(),
+            HumanBytes(average_rate(self.bytes.get(), elapsed)),
+        )
+    }
+}
+
+fn average_rate(bytes: u64, elapsed: Duration) -> u64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        (bytes as f64 / secs) as u64
+    } else {
+        0
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Bursts are kept short, so a limited transfer does not take the link for long
+const BURST_SECS: f64 = 0.1;
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket shared by every transfer it limits, a parent caps them together with others
+#[derive(Clone)]
+pub struct RateLimiter {
+    rate: u64,
+    bucket: Arc<Mutex<TokenBucket>>,
+    parent: Option<Box<RateLimiter>>,
+}
+
+impl RateLimiter {
+    /// Bytes per second
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            bucket: Arc::new(Mutex::new(TokenBucket {
+                tokens: rate as f64 * BURST_SECS,
+                last_refill: Instant::now(),
+            })),
+            parent: None,
+        }
+    }
+
+    /// A limiter for part of the transfers of `parent`, or just the parent without its own rate
+    pub fn child(parent: Option<RateLimiter>, rate: Option<u64>) -> Option<Self> {
+        match rate {
+            Some(rate) => {
+                let mut limiter = Self::new(rate);
+                limiter.parent = parent.map(Box::new);
+                Some(limiter)
+            }
+            None => parent,
+        }
+    }
+
+    pub async fn acquire(&self, bytes: u64) {
+        let mut limiter = Some(self);
+        while let Some(curr) = limiter {
+            let wait = curr.take(bytes);
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+            limiter = curr.parent.as_deref();
+        }
+    }
+
+    /// Takes the tokens at once, going into debt, and tells how long to wait it off
+    fn take(&self, bytes: u64) -> Duration {
+        let rate = self.rate.max(1) as f64;
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(rate * BURST_SECS);
+        bucket.last_refill = now;
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+pub async fn throttle(limiter: Option<&RateLimiter>, bytes: u64) {
+    if let Some(limiter) = limiter {
+        limiter.acquire(bytes).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_spends_the_burst_then_waits_off_the_debt() {
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(limiter.take(50), Duration::ZERO);
+        assert_eq!(limiter.take(50), Duration::ZERO);
+        let wait = limiter.take(100);
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn take_refills_no_more_than_the_burst() {
+        let limiter = RateLimiter::new(1000);
+        limiter.bucket.lock().unwrap().last_refill -= Duration::from_secs(10);
+        assert_eq!(limiter.take(100), Duration::ZERO);
+        assert!(limiter.take(100) > Duration::from_millis(90));
+    }
+
+    #[test]
+    fn take_with_zero_rate_still_ends() {
+        let limiter = RateLimiter::new(0);
+        assert!(limiter.take(1) <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn child_keeps_the_parent() {
+        assert!(RateLimiter::child(None, None).is_none());
+        let parent = RateLimiter::new(1000);
+        let limiter = RateLimiter::child(Some(parent.clone()), None).unwrap();
+        assert_eq!(limiter.rate, 1000);
+        assert!(limiter.parent.is_none());
+        let limiter = RateLimiter::child(Some(parent), Some(10)).unwrap();
+        assert_eq!(limiter.rate, 10);
+        assert_eq!(limiter.parent.unwrap().rate, 1000);
+    }
+}
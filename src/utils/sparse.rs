@@ -0,0 +1,107 @@
+use anyhow::Result;
+use std::fs::File;
+#[cfg(target_os = "linux")]
+use std::{cmp::min, io, os::fd::AsRawFd};
+
+/// The ranges of a file that hold data, the rest of `offset..offset + len` are holes
+#[cfg(target_os = "linux")]
+pub fn data_segments(file: &File, offset: u64, len: u64) -> Result<Vec<(u64, u64)>> {
+    let fd = file.as_raw_fd();
+    let end = offset + len;
+    let mut segments = Vec::new();
+    let mut pos = offset;
+    while pos < end {
+        // only the file offset of the fd is moved, and data is always read at a position
+        let data = unsafe { libc::lseek(fd, pos as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(libc::ENXIO) {
+                // nothing but holes up to the end of file
+                break;
+            }
+            return Err(e.into());
+        }
+        let data = data as u64;
+        if data >= end {
+            break;
+        }
+        let hole = unsafe { libc::lseek(fd, data as libc::off_t, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let hole = min(hole as u64, end);
+        segments.push((data, hole - data));
+        pos = hole;
+    }
+    Ok(segments)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn data_segments(_file: &File, offset: u64, len: u64) -> Result<Vec<(u64, u64)>> {
+    Ok(vec![(offset, len)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::FileExt;
+
+    const BLOCK: u64 = 64 * 1024;
+
+    fn test_file(name: &str) -> (std::path::PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("lant-test-{}-{name}", std::process::id()));
+        let file = File::options()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    /// Segments are ordered, apart and inside the range, whether or not the fs keeps holes
+    fn check_segments(segments: &[(u64, u64)], offset: u64, len: u64) {
+        let mut end = offset;
+        for (segment_offset, segment_len) in segments {
+            assert!(*segment_offset >= end && *segment_len > 0);
+            end = segment_offset + segment_len;
+        }
+        assert!(end <= offset + len);
+    }
+
+    fn covers(segments: &[(u64, u64)], offset: u64, len: u64) -> bool {
+        segments
+            .iter()
+            .any(|(start, size)| *start <= offset && offset + len <= start + size)
+    }
+
+    #[test]
+    fn data_segments_of_a_file_with_holes() {
+        let (path, file) = test_file("sparse");
+        file.set_len(4 * BLOCK).unwrap();
+        file.write_all_at(&[1; BLOCK as usize], BLOCK).unwrap();
+        file.write_all_at(&[2; 10], 3 * BLOCK).unwrap();
+
+        let segments = data_segments(&file, 0, 4 * BLOCK).unwrap();
+        check_segments(&segments, 0, 4 * BLOCK);
+        assert!(covers(&segments, BLOCK, BLOCK));
+        assert!(covers(&segments, 3 * BLOCK, 10));
+
+        // a range is cut to its own bounds
+        let segments = data_segments(&file, BLOCK + 100, BLOCK).unwrap();
+        check_segments(&segments, BLOCK + 100, BLOCK);
+        assert!(covers(&segments, BLOCK + 100, BLOCK - 100));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn data_segments_of_a_full_file() {
+        let (path, file) = test_file("full");
+        file.write_all_at(&[1; 1000], 0).unwrap();
+        assert_eq!(data_segments(&file, 0, 1000).unwrap(), vec![(0, 1000)]);
+        assert_eq!(data_segments(&file, 200, 300).unwrap(), vec![(200, 300)]);
+        fs::remove_file(path).unwrap();
+    }
+}
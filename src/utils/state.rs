@@ -0,0 +1,51 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Kept in the local dir of a two-way sync, and never synced itself
+pub const SYNC_STATE_FILE_NAME: &str = ".lant-sync.json";
+
+/// The version of a file both sides agreed on at the last sync
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyncedFile {
+    pub digest: String,
+    pub local_size: u64,
+    pub local_mtime: u64,
+    pub remote_size: u64,
+    pub remote_mtime: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct SyncState {
+    #[serde(default)]
+    peers: BTreeMap<String, BTreeMap<PathBuf, SyncedFile>>,
+}
+
+impl SyncState {
+    pub fn load(local_dir: &Path) -> Result<Self> {
+        let state_file_path = local_dir.join(SYNC_STATE_FILE_NAME);
+        if !state_file_path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_slice(&fs::read(state_file_path)?)?)
+    }
+
+    pub fn save(&self, local_dir: &Path) -> Result<()> {
+        // replace the old state at once, a crash never leaves half of it
+        let state_file_path = local_dir.join(SYNC_STATE_FILE_NAME);
+        let temp_file_path = state_file_path.with_extension("json.tmp");
+        fs::write(&temp_file_path, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(temp_file_path, state_file_path)?;
+        Ok(())
+    }
+
+    pub fn files(&self, peer: &str) -> BTreeMap<PathBuf, SyncedFile> {
+        self.peers.get(peer).cloned().unwrap_or_default()
+    }
+
+    pub fn set_files(&mut self, peer: &str, files: BTreeMap<PathBuf, SyncedFile>) {
+        self.peers.insert(peer.to_string(), files);
+    }
+}
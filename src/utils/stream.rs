@@ -0,0 +1,492 @@
+use crate::message::{MessagePayloadSize, MAX_REQUEST_PAYLOAD_SIZE};
+use crate::utils::codec::ChunkCodec;
+use crate::utils::rate::{throttle, RateLimiter};
+use crate::utils::sparse::data_segments;
+use anyhow::{anyhow, Result};
+use std::cmp::min;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+static ZEROS: [u8; STREAM_BUFFER_SIZE] = [0; STREAM_BUFFER_SIZE];
+
+/// A sparse chunk starts with its length and the file length,
+/// then each data segment follows its offset and length
+const SIZE_OF_SPARSE_HEADER: u64 = 2 * size_of::<u64>() as u64;
+
+const SIZE_OF_SEGMENT_HEADER: u64 = 2 * size_of::<u64>() as u64;
+
+pub struct FileRange<'a> {
+    file: &'a File,
+    offset: u64,
+    len: u64,
+}
+
+impl<'a> FileRange<'a> {
+    pub fn new(file: &'a File, offset: u64, len: u64) -> Self {
+        Self { file, offset, len }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.len
+    }
+
+    pub fn checksum(&self) -> Result<u32> {
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+        let mut offset = self.offset;
+        let end = self.offset + self.len;
+        while offset < end {
+            let size = min(STREAM_BUFFER_SIZE as u64, end - offset) as usize;
+            self.file.read_exact_at(&mut buffer[..size], offset)?;
+            hasher.update(&buffer[..size]);
+            offset += size as u64;
+        }
+        Ok(hasher.finalize())
+    }
+
+    pub async fn send_to(
+        &self,
+        ss: &mut quinn::SendStream,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<()> {
+        let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+        let mut offset = self.offset;
+        let end = self.offset + self.len;
+        while offset < end {
+            let size = min(STREAM_BUFFER_SIZE as u64, end - offset) as usize;
+            self.file.read_exact_at(&mut buffer[..size], offset)?;
+            throttle(limiter, size as u64).await;
+            ss.write_all(&buffer[..size]).await?;
+            offset += size as u64;
+        }
+        Ok(())
+    }
+
+    pub async fn recv_from<R: AsyncRead + Unpin>(
+        &self,
+        rs: &mut R,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<u32> {
+        let mut hasher = crc32fast::Hasher::new();
+        self.recv_hashed(rs, &mut hasher, limiter).await?;
+        Ok(hasher.finalize())
+    }
+
+    async fn recv_hashed<R: AsyncRead + Unpin>(
+        &self,
+        rs: &mut R,
+        hasher: &mut crc32fast::Hasher,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<()> {
+        let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+        let mut offset = self.offset;
+        let end = self.offset + self.len;
+        while offset < end {
+            let size = min(STREAM_BUFFER_SIZE as u64, end - offset) as usize;
+            throttle(limiter, size as u64).await;
+            rs.read_exact(&mut buffer[..size]).await?;
+            hasher.update(&buffer[..size]);
+            self.file.write_all_at(&buffer[..size], offset)?;
+            offset += size as u64;
+        }
+        Ok(())
+    }
+
+    /// Receive `data_size` bytes in the codec, the decoded data must fit in the range. Data is
+    /// written as it is decoded, so the caller compares the checksum and has a mismatch resent
+    pub async fn recv_encoded_from<R: AsyncRead + Unpin>(
+        &self,
+        rs: &mut R,
+        codec: ChunkCodec,
+        data_size: u64,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<u32> {
+        match codec {
+            ChunkCodec::Raw => {
+                if data_size > self.len {
+                    return Err(anyhow!("chunk data too large, size={data_size}"));
+                }
+                return FileRange::new(self.file, self.offset, data_size)
+                    .recv_from(rs, limiter)
+                    .await;
+            }
+            ChunkCodec::Sparse => return self.recv_sparse_from(rs, data_size, limiter).await,
+            ChunkCodec::Zstd => {}
+        }
+
+        let mut decoder = zstd::stream::write::Decoder::new(RangeWriter::new(self))?;
+        let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+        let mut received = 0;
+        while received < data_size {
+            let size = min(STREAM_BUFFER_SIZE as u64, data_size - received) as usize;
+            throttle(limiter, size as u64).await;
+            rs.read_exact(&mut buffer[..size]).await?;
+            decoder.write_all(&buffer[..size])?;
+            received += size as u64;
+        }
+        decoder.flush()?;
+        Ok(decoder.into_inner().hasher.finalize())
+    }
+
+    async fn recv_sparse_from<R: AsyncRead + Unpin>(
+        &self,
+        rs: &mut R,
+        data_size: u64,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<u32> {
+        let mut header = [0; SIZE_OF_SPARSE_HEADER as usize];
+        rs.read_exact(&mut header).await?;
+        let (len_bytes, file_len_bytes) = header.split_at(size_of::<u64>());
+        let len = u64::from_le_bytes(len_bytes.try_into()?);
+        let file_len = u64::from_le_bytes(file_len_bytes.try_into()?);
+        if len > self.len || data_size > self.len {
+            return Err(anyhow!("chunk data too large, size={data_size}"));
+        }
+
+        // write the data segments, the holes between them are only checksummed
+        let mut hasher = crc32fast::Hasher::new();
+        let mut received = SIZE_OF_SPARSE_HEADER;
+        let mut hashed = 0;
+        while received < data_size {
+            let mut header = [0; SIZE_OF_SEGMENT_HEADER as usize];
+            rs.read_exact(&mut header).await?;
+            let (offset_bytes, len_bytes) = header.split_at(size_of::<u64>());
+            let segment_offset = u64::from_le_bytes(offset_bytes.try_into()?);
+            let segment_len = u64::from_le_bytes(len_bytes.try_into()?);
+            if segment_offset < hashed || segment_offset + segment_len > len {
+                return Err(anyhow!(
+                    "chunk segment out of range, offset={segment_offset}"
+                ));
+            }
+            hash_zeros(&mut hasher, segment_offset - hashed);
+            FileRange::new(self.file, self.offset + segment_offset, segment_len)
+                .recv_hashed(rs, &mut hasher, limiter)
+                .await?;
+            hashed = segment_offset + segment_len;
+            received += SIZE_OF_SEGMENT_HEADER + segment_len;
+        }
+        if received != data_size {
+            return Err(anyhow!("chunk data size error, size={data_size}"));
+        }
+        hash_zeros(&mut hasher, len - hashed);
+
+        // a hole at the end of file still makes up the file length
+        let end = self.offset + len;
+        if hashed < len && end == file_len {
+            self.file.write_all_at(&[0], end - 1)?;
+        }
+        Ok(hasher.finalize())
+    }
+
+    fn compress(&self) -> Result<Option<Vec<u8>>> {
+        let mut encoder =
+            zstd::stream::write::Encoder::new(Vec::new(), zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+        let mut offset = self.offset;
+        let end = self.offset + self.len;
+        while offset < end {
+            let size = min(STREAM_BUFFER_SIZE as u64, end - offset) as usize;
+            self.file.read_exact_at(&mut buffer[..size], offset)?;
+            encoder.write_all(&buffer[..size])?;
+            offset += size as u64;
+
+            // give up early once the data does not shrink
+            if encoder.get_ref().len() as u64 >= self.len {
+                return Ok(None);
+            }
+        }
+        let compressed = encoder.finish()?;
+        Ok(((compressed.len() as u64) < self.len).then_some(compressed))
+    }
+}
+
+fn hash_zeros(hasher: &mut crc32fast::Hasher, len: u64) {
+    let mut hashed = 0;
+    while hashed < len {
+        let size = min(STREAM_BUFFER_SIZE as u64, len - hashed) as usize;
+        hasher.update(&ZEROS[..size]);
+        hashed += size as u64;
+    }
+}
+
+/// Writes decoded data into a file range, and checksums it on the way
+struct RangeWriter<'a> {
+    range: &'a FileRange<'a>,
+    written: u64,
+    hasher: crc32fast::Hasher,
+}
+
+impl<'a> RangeWriter<'a> {
+    fn new(range: &'a FileRange<'a>) -> Self {
+        Self {
+            range,
+            written: 0,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+}
+
+impl Write for RangeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() as u64 > self.range.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decoded chunk data too large",
+            ));
+        }
+        self.range
+            .file
+            .write_all_at(buf, self.range.offset + self.written)?;
+        self.hasher.update(buf);
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Chunk data as sent on the wire, compressed or without holes only when it shrinks
+pub enum EncodedChunk<'a> {
+    Raw(&'a FileRange<'a>),
+    Sparse(&'a FileRange<'a>, Vec<(u64, u64)>),
+    /// Data read into memory from a source that can not seek
+    Bytes(Vec<u8>, ChunkCodec),
+}
+
+impl<'a> EncodedChunk<'a> {
+    /// Holes are skipped before compressing, if the receiver takes sparse chunks
+    pub fn new(range: &'a FileRange<'a>, codec: ChunkCodec, sparse: bool) -> Result<Self> {
+        if sparse {
+            let segments = data_segments(range.file, range.offset, range.len)?;
+            if sparse_size(&segments) < range.len {
+                return Ok(Self::Sparse(range, segments));
+            }
+        }
+        let compressed = match codec {
+            ChunkCodec::Zstd => range.compress()?,
+            ChunkCodec::Raw | ChunkCodec::Sparse => None,
+        };
+        Ok(match compressed {
+            Some(data) => Self::Bytes(data, ChunkCodec::Zstd),
+            None => Self::Raw(range),
+        })
+    }
+
+    pub fn from_bytes(data: Vec<u8>, codec: ChunkCodec) -> Result<Self> {
+        if codec == ChunkCodec::Zstd {
+            let compressed = zstd::bulk::compress(&data, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            if compressed.len() < data.len() {
+                return Ok(Self::Bytes(compressed, ChunkCodec::Zstd));
+            }
+        }
+        Ok(Self::Bytes(data, ChunkCodec::Raw))
+    }
+
+    pub fn codec(&self) -> ChunkCodec {
+        match self {
+            Self::Raw(_) => ChunkCodec::Raw,
+            Self::Sparse(..) => ChunkCodec::Sparse,
+            Self::Bytes(_, codec) => *codec,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Raw(range) => range.size(),
+            Self::Sparse(_, segments) => sparse_size(segments),
+            Self::Bytes(data, _) => data.len() as u64,
+        }
+    }
+
+    pub async fn send_to(
+        &self,
+        ss: &mut quinn::SendStream,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<()> {
+        match self {
+            Self::Raw(range) => range.send_to(ss, limiter).await,
+            Self::Sparse(range, segments) => {
+                ss.write_all(&range.len.to_le_bytes()).await?;
+                ss.write_all(&range.file.metadata()?.len().to_le_bytes())
+                    .await?;
+                for (offset, len) in segments {
+                    ss.write_all(&(offset - range.offset).to_le_bytes()).await?;
+                    ss.write_all(&len.to_le_bytes()).await?;
+                    FileRange::new(range.file, *offset, *len)
+                        .send_to(ss, limiter)
+                        .await?;
+                }
+                Ok(())
+            }
+            Self::Bytes(data, _) => {
+                for buffer in data.chunks(STREAM_BUFFER_SIZE) {
+                    throttle(limiter, buffer.len() as u64).await;
+                    ss.write_all(buffer).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Receive `data_size` bytes in the codec into memory, for a sink that can not seek
+pub async fn recv_encoded_bytes(
+    rs: &mut quinn::RecvStream,
+    codec: ChunkCodec,
+    data_size: u64,
+    max_len: u64,
+    limiter: Option<&RateLimiter>,
+) -> Result<Vec<u8>> {
+    if data_size > max_len {
+        return Err(anyhow!("chunk data too large, size={data_size}"));
+    }
+    let mut data = vec![0; data_size as usize];
+    for buffer in data.chunks_mut(STREAM_BUFFER_SIZE) {
+        throttle(limiter, buffer.len() as u64).await;
+        rs.read_exact(buffer).await?;
+    }
+    match codec {
+        ChunkCodec::Raw => Ok(data),
+        ChunkCodec::Zstd => Ok(zstd::bulk::decompress(&data, max_len as usize)?),
+        ChunkCodec::Sparse => Err(anyhow!("sparse chunk not expected")),
+    }
+}
+
+fn sparse_size(segments: &[(u64, u64)]) -> u64 {
+    SIZE_OF_SPARSE_HEADER
+        + segments
+            .iter()
+            .map(|(_, len)| SIZE_OF_SEGMENT_HEADER + len)
+            .sum::<u64>()
+}
+
+pub async fn read_request_payload(
+    rs: &mut quinn::RecvStream,
+    payload_size: MessagePayloadSize,
+) -> Result<Vec<u8>> {
+    if payload_size > MAX_REQUEST_PAYLOAD_SIZE {
+        return Err(anyhow!("request payload too large, size={payload_size}"));
+    }
+    let mut payload = vec![0; payload_size as usize];
+    rs.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn test_file(name: &str) -> (PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("lant-test-{}-{name}", std::process::id()));
+        let file = File::options()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn recv_raw_chunk_into_its_range() {
+        let (path, file) = test_file("recv-raw");
+        let data = test_data(100_000);
+        let range = FileRange::new(&file, 10, 200_000);
+        let checksum = range
+            .recv_encoded_from(&mut data.as_slice(), ChunkCodec::Raw, 100_000, None)
+            .await
+            .unwrap();
+        assert_eq!(checksum, crc32fast::hash(&data));
+        assert_eq!(&fs::read(&path).unwrap()[10..], data.as_slice());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recv_zstd_chunk_streams_the_decoded_data() {
+        let (path, file) = test_file("recv-zstd");
+        let data = test_data(300_000);
+        let compressed = zstd::bulk::compress(&data, zstd::DEFAULT_COMPRESSION_LEVEL).unwrap();
+        let range = FileRange::new(&file, 0, 300_000);
+        let checksum = range
+            .recv_encoded_from(
+                &mut compressed.as_slice(),
+                ChunkCodec::Zstd,
+                compressed.len() as u64,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(checksum, crc32fast::hash(&data));
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        // decoded data beyond the range is refused
+        let range = FileRange::new(&file, 0, 1000);
+        let result = range
+            .recv_encoded_from(
+                &mut compressed.as_slice(),
+                ChunkCodec::Zstd,
+                compressed.len() as u64,
+                None,
+            )
+            .await;
+        assert!(result.is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recv_sparse_chunk_checksums_its_holes() {
+        let (path, file) = test_file("recv-sparse");
+        let segment = test_data(1000);
+        let mut encoded = Vec::new();
+        encoded.extend(4096u64.to_le_bytes());
+        encoded.extend(4096u64.to_le_bytes());
+        encoded.extend(2048u64.to_le_bytes());
+        encoded.extend(1000u64.to_le_bytes());
+        encoded.extend(&segment);
+
+        let range = FileRange::new(&file, 0, 4096);
+        let checksum = range
+            .recv_encoded_from(
+                &mut encoded.as_slice(),
+                ChunkCodec::Sparse,
+                encoded.len() as u64,
+                None,
+            )
+            .await
+            .unwrap();
+        let mut expected = vec![0; 4096];
+        expected[2048..3048].copy_from_slice(&segment);
+        assert_eq!(checksum, crc32fast::hash(&expected));
+        assert_eq!(fs::read(&path).unwrap(), expected);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recv_corrupted_chunk_tells_by_its_checksum() {
+        let (path, file) = test_file("recv-corrupted");
+        let data = test_data(1000);
+        let mut corrupted = data.clone();
+        corrupted[500] ^= 0xff;
+        let range = FileRange::new(&file, 0, 1000);
+        let checksum = range
+            .recv_encoded_from(&mut corrupted.as_slice(), ChunkCodec::Raw, 1000, None)
+            .await
+            .unwrap();
+        assert_ne!(checksum, crc32fast::hash(&data));
+        fs::remove_file(path).unwrap();
+    }
+}